@@ -0,0 +1,313 @@
+use crate::config::{Config, GuestAccess};
+use crate::csrf::{constant_time_eq, random_token};
+use anyhow::Result;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const SESSION_COOKIE: &str = "rump_session";
+const SESSION_LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Slows down guessing passwords, see `Auth::failed_attempt`.
+const FAILED_LOGIN_DELAY: Duration = Duration::from_secs(1);
+
+/// What a visitor may do. Everyone is `Admin` when authentication is disabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Not logged in, see `GuestAccess`
+    Guest,
+    /// Playback, queue and playlists
+    User,
+    /// Everything, including database updates and the album art cache
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Guest => "guest",
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+struct Session {
+    role: Role,
+    expires: Instant,
+}
+
+/// Logged in browsers, identified by a session cookie. Kept in memory only, so restarting rump
+/// logs everyone out.
+#[derive(Clone)]
+pub struct Auth {
+    config: Arc<Config>,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+    /// Held while a failed attempt waits
+    failures: Arc<Mutex<()>>,
+}
+
+impl Auth {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            config,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            failures: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.admin_password.is_some()
+    }
+
+    pub fn guest_access(&self) -> GuestAccess {
+        self.config.guest_access
+    }
+
+    /// Starts a session for the role `password` belongs to, returns the `Set-Cookie` header
+    /// for it.
+    pub async fn login(&self, password: &str) -> Result<Option<HeaderValue>> {
+        let Some(role) = self.role_of_password(password) else {
+            self.failed_attempt().await;
+            return Ok(None);
+        };
+
+        let id = random_token()?;
+        let mut sessions = self.sessions.lock().await;
+        let now = Instant::now();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(
+            id.clone(),
+            Session {
+                role,
+                expires: now + SESSION_LIFETIME,
+            },
+        );
+        tracing::info!("Logged in as {}", role.as_str());
+
+        Ok(Some(HeaderValue::from_str(&format!(
+            "{SESSION_COOKIE}={id}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+            SESSION_LIFETIME.as_secs()
+        ))?))
+    }
+
+    /// Ends the session of the request, returns the `Set-Cookie` header deleting it.
+    pub async fn logout(&self, headers: &HeaderMap) -> HeaderValue {
+        if let Some(id) = session_id(headers) {
+            self.sessions.lock().await.remove(id);
+        }
+        HeaderValue::from_static("rump_session=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0")
+    }
+
+    fn role_of_password(&self, password: &str) -> Option<Role> {
+        let matches = |configured: &Option<String>| {
+            configured.as_deref().is_some_and(|configured| {
+                constant_time_eq(configured.as_bytes(), password.as_bytes())
+            })
+        };
+        // both are compared, so timing doesn't tell which one was close
+        let admin = matches(&self.config.admin_password);
+        let user = matches(&self.config.user_password);
+        if admin {
+            Some(Role::Admin)
+        } else if user {
+            Some(Role::User)
+        } else {
+            None
+        }
+    }
+
+    /// Delays the response to a wrong password. Failed attempts wait for each other, so
+    /// passwords can't be guessed faster than one per `FAILED_LOGIN_DELAY` by sending many at
+    /// once, whether through the login page or `Authorization` headers.
+    async fn failed_attempt(&self) {
+        let _failures = self.failures.lock().await;
+        tokio::time::sleep(FAILED_LOGIN_DELAY).await;
+    }
+
+    /// Role of the session cookie, or of the password in an `Authorization: Bearer` header
+    /// (for scripts).
+    pub async fn role(&self, headers: &HeaderMap) -> Role {
+        if !self.enabled() {
+            return Role::Admin;
+        }
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if let Some(password) = bearer {
+            return match self.role_of_password(password) {
+                Some(role) => role,
+                None => {
+                    self.failed_attempt().await;
+                    Role::Guest
+                }
+            };
+        }
+        let Some(id) = session_id(headers) else {
+            return Role::Guest;
+        };
+        match self.sessions.lock().await.get(id) {
+            Some(session) if session.expires > Instant::now() => session.role,
+            _ => Role::Guest,
+        }
+    }
+
+    /// Whether guests may use `path` at all; what they may change is up to the routes.
+    fn guest_may_access(&self, path: &str) -> bool {
+        if path == "/login" || path == "/logout" || path.starts_with("/assets/") {
+            return true;
+        }
+        // routes of the other servers are nested under `/s/{name}`
+        let path = match path.strip_prefix("/s/") {
+            Some(rest) => rest.find('/').map_or("/", |i| &rest[i..]),
+            None => path,
+        };
+        match self.config.guest_access {
+            GuestAccess::None => false,
+            GuestAccess::NowPlaying => matches!(
+                path,
+                "/now_playing" | "/now_playing/content" | "/status" | "/cover" | "/api/v1/status"
+            ),
+            GuestAccess::Party => {
+                matches!(
+                    path,
+                    "/now_playing"
+                        | "/now_playing/content"
+                        | "/status"
+                        | "/cover"
+                        | "/api/v1/status"
+                        | "/party"
+                ) || path.starts_with("/party/")
+            }
+            GuestAccess::Browse => true,
+        }
+    }
+}
+
+fn session_id(headers: &HeaderMap) -> Option<&str> {
+    cookie(headers, SESSION_COOKIE)
+}
+
+/// Value of the cookie `name` sent with the request.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| {
+            let (cookie_name, value) = cookie.trim().split_once('=')?;
+            (cookie_name == name).then_some(value)
+        })
+}
+
+/// Middleware resolving the role of every request (available to handlers as an extension) and
+/// keeping guests out of what `guest_access` doesn't allow. Pages they may not see redirect to
+/// the login page.
+pub async fn authenticate(State(auth): State<Auth>, mut request: Request, next: Next) -> Response {
+    let role = auth.role(request.headers()).await;
+    if role == Role::Guest && !auth.guest_may_access(request.uri().path()) {
+        let is_page = request.method() == Method::GET
+            && !request.headers().contains_key("HX-Request")
+            && !request.headers().contains_key(header::UPGRADE);
+        if is_page {
+            return Redirect::to("/login").into_response();
+        }
+        return (StatusCode::UNAUTHORIZED, "Log in to do that").into_response();
+    }
+
+    request.extensions_mut().insert(role);
+    next.run(request).await
+}
+
+/// Route layer refusing requests of roles below `required`, see `authenticate`.
+pub async fn require_role(State(required): State<Role>, request: Request, next: Next) -> Response {
+    let role = request
+        .extensions()
+        .get::<Role>()
+        .copied()
+        .unwrap_or(Role::Guest);
+    if role >= required {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::FORBIDDEN,
+            format!("Log in as {} to do that", required.as_str()),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(guest_access: GuestAccess) -> Auth {
+        Auth::new(Arc::new(Config {
+            admin_password: Some("admin".to_string()),
+            guest_access,
+            ..Config::default()
+        }))
+    }
+
+    #[test]
+    fn guests_may_always_log_in() {
+        let auth = auth(GuestAccess::None);
+        assert!(auth.guest_may_access("/login"));
+        assert!(auth.guest_may_access("/logout"));
+        assert!(auth.guest_may_access("/assets/style.css"));
+    }
+
+    #[test]
+    fn guest_access_none() {
+        let auth = auth(GuestAccess::None);
+        assert!(!auth.guest_may_access("/"));
+        assert!(!auth.guest_may_access("/now_playing"));
+        assert!(!auth.guest_may_access("/s/kitchen/now_playing"));
+        assert!(!auth.guest_may_access("/api/v1/status"));
+    }
+
+    #[test]
+    fn guest_access_now_playing() {
+        let auth = auth(GuestAccess::NowPlaying);
+        assert!(auth.guest_may_access("/now_playing"));
+        assert!(auth.guest_may_access("/cover"));
+        assert!(auth.guest_may_access("/api/v1/status"));
+        assert!(auth.guest_may_access("/s/kitchen/now_playing/content"));
+        assert!(auth.guest_may_access("/s/kitchen/status"));
+        assert!(!auth.guest_may_access("/"));
+        assert!(!auth.guest_may_access("/party"));
+        assert!(!auth.guest_may_access("/s/kitchen/party"));
+        assert!(!auth.guest_may_access("/s/kitchen"));
+        assert!(!auth.guest_may_access("/api/v1/queue"));
+    }
+
+    #[test]
+    fn guest_access_party() {
+        let auth = auth(GuestAccess::Party);
+        assert!(auth.guest_may_access("/now_playing"));
+        assert!(auth.guest_may_access("/party"));
+        assert!(auth.guest_may_access("/party/request"));
+        assert!(auth.guest_may_access("/s/kitchen/party/vote"));
+        assert!(auth.guest_may_access("/s/kitchen/status"));
+        assert!(!auth.guest_may_access("/partyhard"));
+        assert!(!auth.guest_may_access("/library"));
+        assert!(!auth.guest_may_access("/s/kitchen/library"));
+        // a server named like a page doesn't open up its other pages
+        assert!(!auth.guest_may_access("/s/party/library"));
+    }
+
+    #[test]
+    fn guest_access_browse() {
+        let auth = auth(GuestAccess::Browse);
+        assert!(auth.guest_may_access("/"));
+        assert!(auth.guest_may_access("/library"));
+        assert!(auth.guest_may_access("/s/kitchen/search"));
+        assert!(auth.guest_may_access("/api/v1/queue"));
+    }
+}
@@ -1,63 +1,522 @@
+use anyhow::{Context, Result};
 use bytes::Bytes;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
 use std::{
-    collections::{HashMap, VecDeque},
-    sync::Arc,
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::sync::Mutex;
 
-use crate::{error::AppError, mpd::Mpd};
+use crate::{
+    error::AppError,
+    models::{CoverFormat, CoverSize},
+    mpd::{convert_cover, ArtistTag, CoverSource, Mpd},
+};
+
+/// Artist tag, artist and album name.
+pub type AlbumKey = (ArtistTag, String, String);
+
+/// Covers are looked up per album, or per song where albums are ambiguous (compilations with
+/// the same name, songs without album tag).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CoverKey {
+    Album(AlbumKey),
+    /// URI of the song
+    Song(String),
+}
+
+impl CoverKey {
+    /// Query string of the `cover` URL for the key.
+    pub fn query(&self) -> String {
+        let encode = |value: &str| utf8_percent_encode(value, NON_ALPHANUMERIC).to_string();
+        match self {
+            CoverKey::Album((artist_tag, artist, album)) => format!(
+                "artist={}&album={}&artist_tag={}",
+                encode(artist),
+                encode(album),
+                artist_tag.as_str()
+            ),
+            CoverKey::Song(url) => format!("uri={}", encode(url)),
+        }
+    }
+}
+
+impl std::fmt::Display for CoverKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoverKey::Album((_, artist, album)) => write!(f, "{artist}-{album}"),
+            CoverKey::Song(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+/// Size and format a cover is served in; each is cached separately.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Variant {
+    pub size: CoverSize,
+    pub format: CoverFormat,
+}
 
+const EXTENSION: &str = "img";
+/// Covers are written to temporary files first, see `write_file`.
+const TEMP_EXTENSION: &str = "tmp";
+/// How long covers that were not found are served as `fallback_image` before looking again.
+const MISSING_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Album covers stored as files in `dir`, so they survive restarts. The most recently
+/// used `capacity` covers are also kept in memory. When the files grow beyond `max_bytes`, the
+/// least recently used covers are deleted.
+///
+/// Files are named `<hash>.<source>.img`, see [`AlbumArtCache::id`] and [`CoverSource`]. This
+/// is only the index, files are read and written by `get_set` without holding its lock.
 pub struct AlbumArtCache {
-    cache: HashMap<(String, String), Bytes>,
-    keys: VecDeque<(String, String)>,
+    dir: PathBuf,
+    max_bytes: u64,
+    capacity: usize,
+    /// Covers on disk by id
+    entries: HashMap<String, Entry>,
+    memory: HashMap<String, Bytes>,
+    /// Covers that were not found by id, with the time they were looked for. Kept in memory
+    /// only, so that covers added to the library later are picked up.
+    missing: HashMap<String, Instant>,
+    /// `fallback_image` in each variant it was served in
+    fallbacks: HashMap<Variant, Bytes>,
+    total_bytes: u64,
+    /// Incremented on every use, orders `Entry::last_used`
+    clock: u64,
+}
+
+struct Entry {
+    size: u64,
+    last_used: u64,
+    source: CoverSource,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub covers: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
 }
 
 impl AlbumArtCache {
-    pub fn new() -> Self {
-        let cache = HashMap::new();
-        let keys = VecDeque::from([]);
-        Self { cache, keys }
+    /// Opens the cache in `dir` and picks up covers of previous runs. The modification time of
+    /// a file is the last time it was written or read from disk, the most recent ones are loaded
+    /// into memory.
+    pub fn open(dir: PathBuf, max_bytes: u64, capacity: usize) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+
+        let mut files = vec![];
+        let read_dir = std::fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read cache directory {}", dir.display()))?;
+        for entry in read_dir.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            if path.extension().is_some_and(|ext| ext == TEMP_EXTENSION) {
+                // left behind by a crash while writing
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            if path.extension().is_none_or(|ext| ext != EXTENSION) {
+                continue;
+            }
+            let parsed = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.split_once('.'))
+                .and_then(|(id, source)| {
+                    let source = CoverSource::ALL
+                        .into_iter()
+                        .find(|s| s.as_str() == source)?;
+                    Some((id.to_string(), source))
+                });
+            let Some((id, source)) = parsed.filter(|(_, source)| *source != CoverSource::Fallback)
+            else {
+                // written by an older version
+                let _ = std::fs::remove_file(&path);
+                continue;
+            };
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((modified, id, source, metadata.len()));
+        }
+        files.sort_by_key(|(modified, ..)| *modified);
+
+        let mut cache = Self {
+            dir,
+            max_bytes,
+            capacity,
+            entries: HashMap::new(),
+            memory: HashMap::new(),
+            missing: HashMap::new(),
+            fallbacks: HashMap::new(),
+            total_bytes: 0,
+            clock: 0,
+        };
+        for (_, id, source, size) in files {
+            cache.clock += 1;
+            cache.total_bytes += size;
+            let entry = Entry {
+                size,
+                last_used: cache.clock,
+                source,
+            };
+            cache.entries.insert(id, entry);
+        }
+        for path in cache.evict() {
+            let _ = std::fs::remove_file(path);
+        }
+
+        let mut recent: Vec<_> = cache.entries.iter().collect();
+        recent.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.last_used));
+        let recent: Vec<(String, PathBuf)> = recent
+            .into_iter()
+            .take(capacity)
+            .map(|(id, entry)| (id.clone(), cache.path(id, entry.source)))
+            .collect();
+        for (id, path) in recent {
+            if let Ok(buf) = std::fs::read(path) {
+                cache.memory.insert(id, Bytes::from(buf));
+            }
+        }
+
+        tracing::info!(
+            target: "album_art",
+            "{} covers ({} bytes) in {}",
+            cache.entries.len(),
+            cache.total_bytes,
+            cache.dir.display()
+        );
+        Ok(cache)
+    }
+
+    /// Hash of the cover's key, so it stays the same across restarts and is safe to use in file
+    /// names whatever the tags contain.
+    fn id(key: &CoverKey, variant: Variant) -> String {
+        let mut hasher = Sha1::new();
+        let key_parts = match key {
+            CoverKey::Album((artist_tag, artist, album)) => {
+                vec![artist_tag.as_str(), artist.as_str(), album.as_str()]
+            }
+            CoverKey::Song(url) => vec!["uri", url.as_str()],
+        };
+        for part in key_parts
+            .into_iter()
+            .chain([variant.size.as_str(), variant.format.as_str()])
+        {
+            hasher.update(part);
+            hasher.update([0]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn path(&self, id: &str, source: CoverSource) -> PathBuf {
+        self.dir
+            .join(format!("{id}.{}.{EXTENSION}", source.as_str()))
+    }
+
+    fn lookup(&mut self, id: &str, variant: Variant) -> Option<Lookup> {
+        if let Some(looked_for) = self.missing.get(id) {
+            match self.fallbacks.get(&variant) {
+                Some(fallback) if looked_for.elapsed() < MISSING_TTL => {
+                    return Some(Lookup::Memory(fallback.clone(), CoverSource::Fallback));
+                }
+                _ => {
+                    self.missing.remove(id);
+                }
+            }
+        }
+
+        self.clock += 1;
+        let entry = self.entries.get_mut(id)?;
+        entry.last_used = self.clock;
+        let source = entry.source;
+        match self.memory.get(id) {
+            Some(cached) => Some(Lookup::Memory(cached.clone(), source)),
+            None => Some(Lookup::Disk(self.path(id, source), source)),
+        }
     }
 
-    pub fn get(&self, key: &(String, String)) -> Option<Bytes> {
-        self.cache.get(key).cloned()
+    /// Adds a cover that was just written to its file. Returns the files to delete: the cover
+    /// of another source it replaces and those evicted to stay within `max_bytes`.
+    fn insert(&mut self, id: String, value: Bytes, source: CoverSource) -> Vec<PathBuf> {
+        self.missing.remove(&id);
+        let mut obsolete = vec![];
+        if let Some(old) = self.entries.get(&id) {
+            if old.source != source {
+                obsolete.push(self.path(&id, old.source));
+            }
+        }
+
+        self.clock += 1;
+        let size = value.len() as u64;
+        let old = self.entries.insert(
+            id.clone(),
+            Entry {
+                size,
+                last_used: self.clock,
+                source,
+            },
+        );
+        self.total_bytes = self.total_bytes - old.map_or(0, |old| old.size) + size;
+        self.remember(id, value);
+        obsolete.extend(self.evict());
+        obsolete
     }
 
-    pub fn set(&mut self, key: (String, String), value: Bytes) {
-        let old_val = self.cache.insert(key.clone(), value);
-        if old_val.is_none() {
-            // new value was added
-            tracing::debug!(target: "album_art", "caching new value {}-{}", key.0, key.1);
-            self.keys.push_back(key);
+    /// Remembers that there is no cover, returns the files of a cover that was found before.
+    fn insert_missing(&mut self, id: String, variant: Variant, fallback: Bytes) -> Vec<PathBuf> {
+        let now = Instant::now();
+        self.missing
+            .retain(|_, looked_for| now.duration_since(*looked_for) < MISSING_TTL);
+        self.fallbacks.insert(variant, fallback);
+        let obsolete = self
+            .entries
+            .get(&id)
+            .map(|entry| self.path(&id, entry.source));
+        self.forget(&id);
+        self.missing.insert(id, now);
+        obsolete.into_iter().collect()
+    }
+
+    /// Forgets all covers, returns their files.
+    fn clear(&mut self) -> Vec<PathBuf> {
+        let files = self
+            .entries
+            .iter()
+            .map(|(id, entry)| self.path(id, entry.source))
+            .collect();
+        self.entries.clear();
+        self.memory.clear();
+        self.missing.clear();
+        self.total_bytes = 0;
+        files
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            covers: self.entries.len(),
+            bytes: self.total_bytes,
+            max_bytes: self.max_bytes,
+        }
+    }
+
+    fn remember(&mut self, id: String, value: Bytes) {
+        if !self.entries.contains_key(&id) {
+            // deleted while its file was read
+            return;
+        }
+        self.memory.insert(id, value);
+        while self.memory.len() > self.capacity {
+            let Some(oldest) = self.least_recently_used(self.memory.keys()) else {
+                break;
+            };
+            self.memory.remove(&oldest);
+        }
+    }
 
-            while self.keys.len() > 100 {
-                let to_delete = self.keys.pop_front().unwrap();
-                tracing::debug!(target: "album_art", "removing cached value {}-{}", to_delete.0, to_delete.1);
-                self.cache.remove(&to_delete);
+    /// Returns the files of the evicted covers.
+    fn evict(&mut self) -> Vec<PathBuf> {
+        let mut evicted = vec![];
+        while self.total_bytes > self.max_bytes {
+            let Some(oldest) = self.least_recently_used(self.entries.keys()) else {
+                break;
+            };
+            tracing::debug!(target: "album_art", "removing cached value {oldest}");
+            if let Some(entry) = self.entries.get(&oldest) {
+                evicted.push(self.path(&oldest, entry.source));
             }
+            self.forget(&oldest);
         }
+        evicted
+    }
+
+    fn least_recently_used<'a>(&self, ids: impl Iterator<Item = &'a String>) -> Option<String> {
+        ids.min_by_key(|id| self.entries.get(*id).map_or(0, |entry| entry.last_used))
+            .cloned()
     }
+
+    fn forget(&mut self, id: &str) {
+        if let Some(entry) = self.entries.remove(id) {
+            self.total_bytes -= entry.size;
+        }
+        self.memory.remove(id);
+    }
+}
+
+/// Where `AlbumArtCache::lookup` found a cover.
+enum Lookup {
+    Memory(Bytes, CoverSource),
+    /// Not in memory, the file has to be read
+    Disk(PathBuf, CoverSource),
+}
+
+/// Reads a cached cover and marks it as used for the next start (failing to do so only affects
+/// eviction order). Covers served from memory are not marked, to spare SD cards a write per use.
+async fn read_file(path: PathBuf) -> Result<Bytes> {
+    tokio::task::spawn_blocking(move || {
+        let buf =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let _ = std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        Ok(Bytes::from(buf))
+    })
+    .await?
 }
 
+/// Writes to a temporary file that is renamed once complete, so that a crash or power cut never
+/// leaves a truncated cover behind.
+async fn write_file(path: PathBuf, value: Bytes) -> Result<()> {
+    // concurrent requests may write the same cover
+    static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+    let temp = path.with_extension(format!(
+        "{}.{TEMP_EXTENSION}",
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    tokio::task::spawn_blocking(move || {
+        let result = std::fs::File::create(&temp)
+            .and_then(|mut file| {
+                std::io::Write::write_all(&mut file, &value)?;
+                file.sync_all()
+            })
+            .and_then(|()| std::fs::rename(&temp, &path));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+        result.with_context(|| format!("Failed to write {}", path.display()))
+    })
+    .await?
+}
+
+async fn remove_files(paths: Vec<PathBuf>) -> Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    tokio::task::spawn_blocking(move || {
+        for path in paths {
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to delete {}", path.display()))
+                }
+            }
+        }
+        Ok(())
+    })
+    .await?
+}
+
+/// Deletes all cached covers.
+pub async fn purge(album_art_cache: &Mutex<AlbumArtCache>) -> Result<()> {
+    let files = album_art_cache.lock().await.clear();
+    remove_files(files).await
+}
+
+/// Cover of `key` from the cache, or from MPD when it isn't cached. Override images take
+/// precedence over cached covers from MPD, so they are looked for first (for songs, this takes
+/// looking up their album).
 pub async fn get_set(
-    key: (String, String),
+    key: CoverKey,
+    variant: Variant,
     album_art_cache: Arc<Mutex<AlbumArtCache>>,
     mpd: &Mpd,
-) -> Result<Bytes, AppError> {
-    {
-        let cache = album_art_cache.lock().await;
-        if let Some(cached) = cache.get(&key) {
-            tracing::debug!(target: "album_art", "returning cached value for {}-{}", key.0, key.1);
-            return Ok(cached);
+) -> Result<(Bytes, CoverSource), AppError> {
+    let override_path = mpd.override_art_path(&key).await?;
+    // cached covers are stale when an override was added or removed since
+    let is_current =
+        |source: CoverSource| (source == CoverSource::Override) == override_path.is_some();
+
+    let id = AlbumArtCache::id(&key, variant);
+    let cached = album_art_cache.lock().await.lookup(&id, variant);
+    match cached {
+        Some(Lookup::Memory(cached, source)) if is_current(source) => {
+            tracing::debug!(target: "album_art", "returning cached value for {key}");
+            return Ok((cached, source));
         }
+        Some(Lookup::Disk(path, source)) if is_current(source) => match read_file(path).await {
+            Ok(cached) => {
+                tracing::debug!(target: "album_art", "returning cached value for {key}");
+                album_art_cache
+                    .lock()
+                    .await
+                    .remember(id.clone(), cached.clone());
+                return Ok((cached, source));
+            }
+            Err(e) => {
+                tracing::warn!(target: "album_art", "{e:#}");
+                album_art_cache.lock().await.forget(&id);
+            }
+        },
+        _ => {}
     }
 
-    let art = mpd.album_art(&key.0, &key.1).await?;
+    let (art, source) = match (override_path, &key) {
+        (Some(path), _) => {
+            let art = tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            (Bytes::from(art), CoverSource::Override)
+        }
+        (None, CoverKey::Album((artist_tag, artist, album))) => {
+            mpd.album_art(*artist_tag, artist, album).await?
+        }
+        (None, CoverKey::Song(url)) => mpd.song_art(url).await?,
+    };
+    let max_size = mpd.cover_size(variant.size);
+    let (art, source) = match convert_cover(art, max_size, variant.format) {
+        Ok(art) => (art, source),
+        Err(e) => {
+            // e.g. CMYK JPEG or AVIF
+            tracing::warn!(
+                target: "album_art",
+                "failed to decode the {} cover of {key}: {e}",
+                source.as_str()
+            );
+            let fallback = mpd.fallback_image().await?;
+            (
+                convert_cover(fallback, max_size, variant.format)?,
+                CoverSource::Fallback,
+            )
+        }
+    };
+
+    if source == CoverSource::Fallback {
+        let obsolete = album_art_cache
+            .lock()
+            .await
+            .insert_missing(id, variant, art.clone());
+        if let Err(e) = remove_files(obsolete).await {
+            tracing::warn!(target: "album_art", "{e:#}");
+        }
+        return Ok((art, source));
+    }
 
-    {
-        let mut cache = album_art_cache.lock().await;
-        cache.set(key, art.clone());
+    let path = album_art_cache.lock().await.path(&id, source);
+    match write_file(path, art.clone()).await {
+        Ok(()) => {
+            tracing::debug!(target: "album_art", "caching new value {key}");
+            let obsolete = album_art_cache.lock().await.insert(id, art.clone(), source);
+            if let Err(e) = remove_files(obsolete).await {
+                tracing::warn!(target: "album_art", "{e:#}");
+            }
+        }
+        Err(e) => tracing::warn!(target: "album_art", "{e:#}"),
     }
 
-    Ok(art)
+    Ok((art, source))
 }
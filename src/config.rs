@@ -0,0 +1,373 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Settings file loaded when `--config` is not given (only if it exists).
+const DEFAULT_CONFIG_FILE: &str = "rump.toml";
+
+#[derive(Parser)]
+#[command(version, about = "Music Player Daemon web client")]
+pub struct Args {
+    /// Path to TOML settings file
+    #[arg(short, long, env = "RUMP_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address the web server listens on
+    #[arg(long, env = "RUMP_LISTEN")]
+    pub listen: Option<String>,
+
+    /// MPD host; accepts `host`, `password@host`, `/path/to/socket` and `password@/path/to/socket`.
+    /// Ignored when `servers` are listed in the settings file
+    #[arg(long, env = "MPD_HOST")]
+    pub mpd_host: Option<String>,
+
+    /// MPD port. Ignored when `servers` are listed in the settings file
+    #[arg(long, env = "MPD_PORT")]
+    pub mpd_port: Option<u16>,
+
+    /// Number of album covers kept in memory
+    #[arg(long, env = "RUMP_CACHE_SIZE")]
+    pub cache_size: Option<usize>,
+
+    /// Directory album covers are cached in across restarts
+    #[arg(long, env = "RUMP_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Size limit of the cache directory in bytes; least recently used covers are deleted first
+    #[arg(long, env = "RUMP_CACHE_MAX_BYTES")]
+    pub cache_max_bytes: Option<u64>,
+
+    /// Edge length (in pixels) of cover thumbnails
+    #[arg(long, env = "RUMP_ALBUM_ART_SIZE")]
+    pub album_art_size: Option<u32>,
+
+    /// Edge length (in pixels) of medium sized covers
+    #[arg(long, env = "RUMP_ALBUM_ART_MEDIUM_SIZE")]
+    pub album_art_medium_size: Option<u32>,
+
+    /// Image served when an album has no cover
+    #[arg(long, env = "RUMP_FALLBACK_IMAGE")]
+    pub fallback_image: Option<PathBuf>,
+
+    /// Directory with cover images taking precedence over MPD, stored as
+    /// `<artist>/<album>.<jpg|jpeg|png|webp>`
+    #[arg(long, env = "RUMP_ALBUM_ART_DIR")]
+    pub album_art_dir: Option<PathBuf>,
+
+    /// Directory with static assets (styles, fonts, scripts)
+    #[arg(long, env = "RUMP_ASSETS_DIR")]
+    pub assets_dir: Option<PathBuf>,
+
+    /// File the favourite radio stations are saved to
+    #[arg(long, env = "RUMP_STATIONS_FILE")]
+    pub stations_file: Option<PathBuf>,
+
+    /// Password to log in as admin; enables authentication. Without it, everyone may do
+    /// everything
+    #[arg(long, env = "RUMP_ADMIN_PASSWORD")]
+    pub admin_password: Option<String>,
+
+    /// Password to log in as user (playback and queue, but not e.g. updating the database)
+    #[arg(long, env = "RUMP_USER_PASSWORD")]
+    pub user_password: Option<String>,
+
+    /// What visitors may do without logging in
+    #[arg(long, env = "RUMP_GUEST_ACCESS")]
+    pub guest_access: Option<GuestAccess>,
+
+    /// Guests' song requests in party mode wait for an admin to approve them
+    #[arg(long, env = "RUMP_PARTY_APPROVAL")]
+    pub party_approval: bool,
+
+    /// Number of songs a guest may request in party mode per hour
+    #[arg(long, env = "RUMP_PARTY_REQUESTS_PER_HOUR")]
+    pub party_requests_per_hour: Option<usize>,
+
+    /// Also accept GET for the web UI's state-changing URLs (e.g. `/control/next`), as before
+    /// they required POST or DELETE. Not protected against cross-site requests
+    #[arg(long, env = "RUMP_LEGACY_GET_ACTIONS")]
+    pub legacy_get_actions: bool,
+
+    /// Print the effective configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: String,
+    #[serde(serialize_with = "redact_host")]
+    pub mpd_host: String,
+    pub mpd_port: u16,
+    pub cache_size: usize,
+    /// One subdirectory per server
+    pub cache_dir: PathBuf,
+    pub cache_max_bytes: u64,
+    pub album_art_size: u32,
+    pub album_art_medium_size: u32,
+    pub fallback_image: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_art_dir: Option<PathBuf>,
+    pub assets_dir: PathBuf,
+    pub stations_file: PathBuf,
+    pub legacy_get_actions: bool,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "redact")]
+    pub admin_password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "redact")]
+    pub user_password: Option<String>,
+    pub guest_access: GuestAccess,
+    pub party_approval: bool,
+    pub party_requests_per_hour: usize,
+    /// Named MPD servers (e.g. one per room); when empty, a single server named `default` is
+    /// created from `mpd_host` and `mpd_port`.
+    pub servers: Vec<ServerConfig>,
+}
+
+/// What visitors who are not logged in may do, when authentication is enabled.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum GuestAccess {
+    /// Nothing but logging in
+    None,
+    /// Watch the now playing page
+    NowPlaying,
+    /// Watch the now playing page and request songs in party mode
+    Party,
+    /// Browse the library and append to the queue
+    Browse,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub name: String,
+    /// Same forms as `mpd_host`
+    #[serde(serialize_with = "redact_host")]
+    pub host: String,
+    #[serde(default = "default_mpd_port")]
+    pub port: u16,
+}
+
+/// Keeps passwords out of `--print-config`.
+fn redact<S: serde::Serializer>(_: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("<redacted>")
+}
+
+/// Keeps the password of `password@host` out of `--print-config`.
+fn redact_host<S: serde::Serializer>(host: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    match host.rsplit_once('@') {
+        Some((password, host)) if !password.is_empty() => {
+            serializer.serialize_str(&format!("<redacted>@{host}"))
+        }
+        _ => serializer.serialize_str(host),
+    }
+}
+
+fn default_mpd_port() -> u16 {
+    6600
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: "0.0.0.0:8000".to_string(),
+            mpd_host: "localhost".to_string(),
+            mpd_port: default_mpd_port(),
+            cache_size: 100,
+            cache_dir: PathBuf::from("cache"),
+            cache_max_bytes: 64 * 1024 * 1024,
+            album_art_size: 256,
+            album_art_medium_size: 640,
+            fallback_image: PathBuf::from("assets/lp.png"),
+            album_art_dir: None,
+            assets_dir: PathBuf::from("assets"),
+            stations_file: PathBuf::from("stations.toml"),
+            legacy_get_actions: false,
+            admin_password: None,
+            user_password: None,
+            guest_access: GuestAccess::Browse,
+            party_approval: false,
+            party_requests_per_hour: 5,
+            servers: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// Builds the effective configuration. Precedence (lowest to highest): built-in defaults,
+    /// settings file, environment variables, command line flags.
+    pub fn load(args: &Args) -> Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+
+        if let Some(listen) = &args.listen {
+            config.listen = listen.clone();
+        }
+        if let Some(mpd_host) = &args.mpd_host {
+            config.mpd_host = mpd_host.clone();
+        }
+        if let Some(mpd_port) = args.mpd_port {
+            config.mpd_port = mpd_port;
+        }
+        if let Some(cache_size) = args.cache_size {
+            config.cache_size = cache_size;
+        }
+        if let Some(cache_dir) = &args.cache_dir {
+            config.cache_dir = cache_dir.clone();
+        }
+        if let Some(cache_max_bytes) = args.cache_max_bytes {
+            config.cache_max_bytes = cache_max_bytes;
+        }
+        if let Some(album_art_size) = args.album_art_size {
+            config.album_art_size = album_art_size;
+        }
+        if let Some(album_art_medium_size) = args.album_art_medium_size {
+            config.album_art_medium_size = album_art_medium_size;
+        }
+        if let Some(fallback_image) = &args.fallback_image {
+            config.fallback_image = fallback_image.clone();
+        }
+        if let Some(album_art_dir) = &args.album_art_dir {
+            config.album_art_dir = Some(album_art_dir.clone());
+        }
+        if let Some(assets_dir) = &args.assets_dir {
+            config.assets_dir = assets_dir.clone();
+        }
+        if let Some(stations_file) = &args.stations_file {
+            config.stations_file = stations_file.clone();
+        }
+        if args.legacy_get_actions {
+            config.legacy_get_actions = true;
+        }
+        if let Some(admin_password) = &args.admin_password {
+            config.admin_password = Some(admin_password.clone());
+        }
+        if let Some(user_password) = &args.user_password {
+            config.user_password = Some(user_password.clone());
+        }
+        if let Some(guest_access) = args.guest_access {
+            config.guest_access = guest_access;
+        }
+        if args.party_approval {
+            config.party_approval = true;
+        }
+        if let Some(party_requests_per_hour) = args.party_requests_per_hour {
+            config.party_requests_per_hour = party_requests_per_hour;
+        }
+
+        if config.user_password.is_some() && config.admin_password.is_none() {
+            bail!("user_password requires admin_password to be set");
+        }
+
+        for (i, server) in config.servers.iter().enumerate() {
+            if server.name.is_empty()
+                || !server
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!(
+                    "Invalid server name {:?}, use only letters, digits, '-' and '_'",
+                    server.name
+                );
+            }
+            if config.servers[..i].iter().any(|s| s.name == server.name) {
+                bail!("Duplicate server name {:?}", server.name);
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// MPD servers to connect to, the first one is served at the root URL.
+    pub fn servers(&self) -> Vec<ServerConfig> {
+        if self.servers.is_empty() {
+            vec![ServerConfig {
+                name: "default".to_string(),
+                host: self.mpd_host.clone(),
+                port: self.mpd_port,
+            }]
+        } else {
+            self.servers.clone()
+        }
+    }
+}
+
+impl ServerConfig {
+    fn split_host(&self) -> (Option<&str>, &str) {
+        match self.host.rsplit_once('@') {
+            Some((password, host)) if !password.is_empty() => (Some(password), host),
+            Some((_, host)) => (None, host),
+            None => (None, &self.host),
+        }
+    }
+
+    pub fn mpd_password(&self) -> Option<&str> {
+        self.split_host().0
+    }
+
+    pub fn mpd_address(&self) -> MpdAddress {
+        let (_, host) = self.split_host();
+        if host.starts_with('/') {
+            MpdAddress::Unix(PathBuf::from(host))
+        } else {
+            MpdAddress::Tcp(format!("{}:{}", host, self.port))
+        }
+    }
+
+    /// Human readable MPD address, never includes the password.
+    pub fn mpd_addr(&self) -> String {
+        match self.mpd_address() {
+            MpdAddress::Tcp(addr) => addr,
+            MpdAddress::Unix(path) => path.display().to_string(),
+        }
+    }
+}
+
+pub enum MpdAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_toml_redacts_passwords() {
+        let config = Config {
+            mpd_host: "hunter2@localhost".to_string(),
+            admin_password: Some("admin".to_string()),
+            servers: vec![ServerConfig {
+                name: "kitchen".to_string(),
+                host: "secret@/run/mpd/socket".to_string(),
+                port: 6600,
+            }],
+            ..Config::default()
+        };
+        let toml = config.to_toml().unwrap();
+        assert!(toml.contains(r#"mpd_host = "<redacted>@localhost""#));
+        assert!(toml.contains(r#"host = "<redacted>@/run/mpd/socket""#));
+        assert!(!toml.contains("hunter2"));
+        assert!(!toml.contains("secret"));
+        assert!(!toml.contains(r#""admin""#));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::config::ServerConfig;
+use crate::mpd::Mpd;
+use crate::snapshot::SnapshotPublisher;
+use mpd_client::client::{ConnectionEvent, ConnectionEvents, Subsystem};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+
+/// Re-read everything at least this often, in case an event was missed.
+const RESYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Delay before the first reconnection attempt, doubled after every failed one.
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub enum ConnectionState {
+    /// Before the first connection attempt finished
+    Connecting,
+    Connected,
+    /// Waiting for the next connection attempt
+    Reconnecting {
+        error: String,
+        /// Failed attempts since the connection was lost
+        attempt: u32,
+        retry_in: Duration,
+    },
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        matches!(self, Self::Connected)
+    }
+}
+
+/// Connects to one server and keeps its connection up: refreshes the snapshot once per change,
+/// then forwards the event to `event_tx`, and reconnects with backoff when the connection is
+/// lost. MPD being unreachable is not an error, the returned state tells whether it is.
+pub fn spawn(
+    server: Arc<ServerConfig>,
+    mpd: Mpd,
+    event_tx: broadcast::Sender<Subsystem>,
+    publisher: SnapshotPublisher,
+) -> watch::Receiver<ConnectionState> {
+    let (state_tx, state_rx) = watch::channel(ConnectionState::Connecting);
+
+    tokio::spawn(async move {
+        let mut attempt = 0;
+        loop {
+            let error = match Mpd::connect(&server).await {
+                Ok((client, connection_events)) => {
+                    tracing::info!("Connected to MPD {}", server.name);
+                    mpd.update_client(client).await;
+                    attempt = 0;
+                    state_tx.send_replace(ConnectionState::Connected);
+
+                    forward_events(&mpd, &event_tx, &publisher, connection_events).await;
+                    tracing::warn!("MPD connection to {} lost", server.name);
+                    "connection lost".to_string()
+                }
+                Err(e) => {
+                    tracing::error!("Failed to connect to MPD {}: {e}", server.name);
+                    e.to_string()
+                }
+            };
+
+            attempt += 1;
+            let retry_in = reconnect_delay(attempt);
+            tracing::info!("Reconnecting to MPD {} in {retry_in:.1?}", server.name);
+            state_tx.send_replace(ConnectionState::Reconnecting {
+                error,
+                attempt,
+                retry_in,
+            });
+            tokio::time::sleep(retry_in).await;
+        }
+    });
+
+    state_rx
+}
+
+/// Returns when the connection is closed.
+async fn forward_events(
+    mpd: &Mpd,
+    event_tx: &broadcast::Sender<Subsystem>,
+    publisher: &SnapshotPublisher,
+    mut connection_events: ConnectionEvents,
+) {
+    publisher.refresh(mpd, None).await;
+
+    loop {
+        match tokio::time::timeout(RESYNC_INTERVAL, connection_events.next()).await {
+            Ok(Some(ConnectionEvent::SubsystemChange(subsystem))) => {
+                publisher.refresh(mpd, Some(&subsystem)).await;
+                let _ = event_tx.send(subsystem);
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => return,
+            Err(_) => publisher.refresh(mpd, None).await,
+        }
+    }
+}
+
+/// Exponential backoff with "equal jitter": a random delay between half and all of
+/// `RECONNECT_MIN_DELAY * 2^(attempt - 1)`, capped at `RECONNECT_MAX_DELAY`, so that clients of a
+/// restarted MPD don't all come back at once.
+fn reconnect_delay(attempt: u32) -> Duration {
+    let delay = RECONNECT_MIN_DELAY
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(RECONNECT_MAX_DELAY);
+    // a fresh `RandomState` is randomly seeded, good enough for jitter
+    let random = RandomState::new().build_hasher().finish();
+    delay / 2 + delay.mul_f64((random as f64 / u64::MAX as f64) / 2.0)
+}
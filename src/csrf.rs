@@ -0,0 +1,324 @@
+use anyhow::Result;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sha1::{Digest, Sha1};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Header the web UI sends the token in (see `hx-headers` in `index.html`).
+const TOKEN_HEADER: &str = "X-CSRF-Token";
+/// Field plain HTML forms send the token in (see `login.html`).
+const TOKEN_FIELD: &str = "csrf_token";
+/// Largest form body read looking for `TOKEN_FIELD`.
+const FORM_LIMIT: usize = 64 * 1024;
+
+/// Random token embedded in every page, created at startup.
+#[derive(Clone)]
+pub struct CsrfToken(Arc<str>);
+
+impl CsrfToken {
+    pub fn generate() -> Result<Self> {
+        Ok(Self(random_token()?.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// HMAC-SHA1 of `message` keyed by the token, hex encoded. Values handed out to browsers
+    /// (e.g. the party client ID) can be checked with it without keeping them.
+    pub fn sign(&self, message: &str) -> String {
+        const BLOCK_SIZE: usize = 64;
+        let mut key = [0; BLOCK_SIZE];
+        // tokens are shorter than a block; longer keys would be hashed first
+        key[..self.0.len()].copy_from_slice(self.0.as_bytes());
+
+        let pad = |byte: u8| key.map(|k| k ^ byte);
+        let inner = Sha1::new()
+            .chain_update(pad(0x36))
+            .chain_update(message)
+            .finalize();
+        let outer = Sha1::new()
+            .chain_update(pad(0x5c))
+            .chain_update(inner)
+            .finalize();
+        format!("{outer:x}")
+    }
+}
+
+/// 128 random bits, hex encoded.
+pub fn random_token() -> Result<String> {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes)?;
+    let mut token = String::new();
+    for byte in bytes {
+        write!(token, "{byte:02x}")?;
+    }
+    Ok(token)
+}
+
+/// Compares two secrets in time depending only on their lengths, so that response times don't
+/// tell how much of a guess was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let difference = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(difference) == 0
+}
+
+/// Middleware refusing state-changing requests from other sites. They are accepted with the
+/// page's token (needed behind proxies rewriting `Host`), from the same origin, or from clients
+/// that are not browsers at all (e.g. scripts using the JSON API), which send neither `Origin`
+/// nor `Sec-Fetch-Site`.
+pub async fn check_origin(
+    State(token): State<CsrfToken>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (request, allowed) = if is_allowed(&request, &token) {
+        (request, true)
+    } else {
+        has_form_token(request, &token).await
+    };
+    if allowed {
+        next.run(request).await
+    } else {
+        tracing::warn!(
+            "Refused cross-site {} {}",
+            request.method(),
+            request.uri().path()
+        );
+        (
+            StatusCode::FORBIDDEN,
+            "Cross-site request refused, try reloading the page",
+        )
+            .into_response()
+    }
+}
+
+/// Whether the request is a form carrying the token in `TOKEN_FIELD`, as plain HTML forms can't
+/// send headers. The body is read for that and put back into the returned request.
+async fn has_form_token(request: Request, token: &CsrfToken) -> (Request, bool) {
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return (request, false);
+    }
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, FORM_LIMIT).await else {
+        return (Request::from_parts(parts, Body::empty()), false);
+    };
+    let allowed = body.split(|&b| b == b'&').any(|field| {
+        field
+            .strip_prefix(TOKEN_FIELD.as_bytes())
+            .and_then(|rest| rest.strip_prefix(b"="))
+            .is_some_and(|value| constant_time_eq(value, token.as_str().as_bytes()))
+    });
+    (Request::from_parts(parts, Body::from(body)), allowed)
+}
+
+fn is_allowed(request: &Request, token: &CsrfToken) -> bool {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return true;
+    }
+
+    let headers = request.headers();
+    if headers
+        .get(TOKEN_HEADER)
+        .is_some_and(|value| constant_time_eq(value.as_bytes(), token.as_str().as_bytes()))
+    {
+        return true;
+    }
+
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        let host = headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| {
+                request
+                    .uri()
+                    .authority()
+                    .map(|authority| authority.as_str())
+            });
+        // `scheme://host[:port]`, or `null` for opaque origins
+        let origin_host = origin
+            .to_str()
+            .ok()
+            .and_then(|origin| origin.split_once("://"))
+            .map(|(_, host)| host);
+        return origin_host.is_some() && origin_host == host;
+    }
+
+    match headers.get("Sec-Fetch-Site") {
+        Some(site) => matches!(site.as_bytes(), b"same-origin" | b"none"),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    fn token() -> CsrfToken {
+        CsrfToken("0123456789abcdef0123456789abcdef".into())
+    }
+
+    fn post(headers: &[(&str, &str)]) -> Request {
+        let mut builder = Request::builder()
+            .method(Method::POST)
+            .uri("/control/next")
+            .header(header::HOST, "rump.local:8000");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn allows_reading_from_anywhere() {
+        let request = Request::builder()
+            .uri("/status")
+            .header(header::ORIGIN, "http://evil.example")
+            .body(Body::empty())
+            .unwrap();
+        assert!(is_allowed(&request, &token()));
+    }
+
+    #[test]
+    fn allows_same_origin() {
+        let request = post(&[("Origin", "http://rump.local:8000")]);
+        assert!(is_allowed(&request, &token()));
+    }
+
+    #[test]
+    fn refuses_other_origins() {
+        assert!(!is_allowed(
+            &post(&[("Origin", "http://evil.example")]),
+            &token()
+        ));
+        // same host, other port
+        assert!(!is_allowed(
+            &post(&[("Origin", "http://rump.local:8080")]),
+            &token()
+        ));
+    }
+
+    #[test]
+    fn refuses_null_origin() {
+        assert!(!is_allowed(&post(&[("Origin", "null")]), &token()));
+    }
+
+    #[test]
+    fn allows_token_whatever_the_origin() {
+        let request = post(&[
+            ("Origin", "http://proxy.example"),
+            (TOKEN_HEADER, "0123456789abcdef0123456789abcdef"),
+        ]);
+        assert!(is_allowed(&request, &token()));
+    }
+
+    #[test]
+    fn refuses_wrong_token_from_other_origin() {
+        let request = post(&[
+            ("Origin", "http://evil.example"),
+            (TOKEN_HEADER, "0123456789abcdef0123456789abcdee"),
+        ]);
+        assert!(!is_allowed(&request, &token()));
+    }
+
+    #[test]
+    fn checks_sec_fetch_site_without_origin() {
+        assert!(is_allowed(
+            &post(&[("Sec-Fetch-Site", "same-origin")]),
+            &token()
+        ));
+        assert!(is_allowed(&post(&[("Sec-Fetch-Site", "none")]), &token()));
+        assert!(!is_allowed(
+            &post(&[("Sec-Fetch-Site", "cross-site")]),
+            &token()
+        ));
+        assert!(!is_allowed(
+            &post(&[("Sec-Fetch-Site", "same-site")]),
+            &token()
+        ));
+    }
+
+    #[test]
+    fn allows_clients_that_are_not_browsers() {
+        assert!(is_allowed(&post(&[]), &token()));
+    }
+
+    fn form(body: &str) -> Request {
+        let mut request = post(&[
+            ("Origin", "http://proxy.example"),
+            ("Content-Type", "application/x-www-form-urlencoded"),
+        ]);
+        *request.body_mut() = Body::from(body.to_string());
+        request
+    }
+
+    #[tokio::test]
+    async fn allows_token_in_forms() {
+        let (request, allowed) = has_form_token(
+            form("password=x&csrf_token=0123456789abcdef0123456789abcdef"),
+            &token(),
+        )
+        .await;
+        assert!(allowed);
+        // the handler still gets the whole form
+        let body = axum::body::to_bytes(request.into_body(), FORM_LIMIT)
+            .await
+            .unwrap();
+        assert!(body.starts_with(b"password=x&"));
+    }
+
+    #[tokio::test]
+    async fn refuses_forms_without_token() {
+        assert!(!has_form_token(form("password=x"), &token()).await.1);
+        assert!(
+            !has_form_token(
+                form("csrf_token=0123456789abcdef0123456789abcdee"),
+                &token()
+            )
+            .await
+            .1
+        );
+        assert!(
+            !has_form_token(
+                form("xcsrf_token=0123456789abcdef0123456789abcdef"),
+                &token()
+            )
+            .await
+            .1
+        );
+    }
+
+    #[test]
+    fn sign_is_hmac_sha1() {
+        // RFC 2202, test case 2
+        assert_eq!(
+            CsrfToken("Jefe".into()).sign("what do ya want for nothing?"),
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+        );
+    }
+
+    #[test]
+    fn constant_time_eq_compares_contents_and_lengths() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -1,21 +1,167 @@
 use axum::{
-    http::StatusCode,
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
     response::{IntoResponse, Response},
+    Json,
 };
+use mpd_client::client::CommandError;
 
+/// Error of a UI handler. Rendered as plain text with an `HX-Trigger` header showing a toast, or
+/// as `{"error": "..."}` when the client accepts JSON (see `negotiate_error`).
 pub struct AppError(pub anyhow::Error);
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let kind = ErrorKind::of(&self.0);
+        let message = kind.message(&self.0);
+        if kind.status_code().is_server_error() {
+            tracing::error!("{:#}", self.0);
+        }
+
+        let mut response = (
+            kind.status_code(),
+            [("HX-Trigger", toast_trigger(&message))],
+            message.clone(),
+        )
+            .into_response();
+        response.extensions_mut().insert(ErrorMessage(message));
+        response
+    }
+}
+
+impl<E> From<E> for AppError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        Self(err.into())
+    }
+}
+
+/// Invalid request parameters; reported as `400 Bad Request`.
+#[derive(Debug)]
+pub struct BadRequest(pub String);
+
+impl std::fmt::Display for BadRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for BadRequest {}
+
+/// Something that was asked for by name does not exist, e.g. an album; reported as
+/// `404 Not Found`. Unknown song ids and playlists are reported by MPD itself.
+#[derive(Debug)]
+pub struct NotFound(pub String);
+
+impl std::fmt::Display for NotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// A client did something too often, e.g. requesting songs in party mode; reported as
+/// `429 Too Many Requests`.
+#[derive(Debug)]
+pub struct TooManyRequests(pub String);
+
+impl std::fmt::Display for TooManyRequests {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TooManyRequests {}
+
+/// What went wrong, as far as the client is concerned.
+enum ErrorKind {
+    BadRequest,
+    NotFound,
+    TooManyRequests,
+    /// MPD refused the command with an `ACK` error
+    Mpd(u64),
+    /// MPD is not connected
+    ConnectionLost,
+    Internal,
+}
+
+impl ErrorKind {
+    fn of(err: &anyhow::Error) -> Self {
+        if err.is::<BadRequest>() {
+            return Self::BadRequest;
+        }
+        if err.is::<NotFound>() {
+            return Self::NotFound;
+        }
+        if err.is::<TooManyRequests>() {
+            return Self::TooManyRequests;
+        }
+        match err.downcast_ref::<CommandError>() {
+            Some(CommandError::ErrorResponse { error, .. }) => Self::Mpd(error.code),
+            Some(CommandError::ConnectionClosed) | Some(CommandError::Protocol(_)) => {
+                Self::ConnectionLost
+            }
+            _ => Self::Internal,
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest => StatusCode::BAD_REQUEST,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::Mpd(code) => match code {
+                // ACK_ERROR_ARG
+                2 => StatusCode::BAD_REQUEST,
+                // ACK_ERROR_PASSWORD, ACK_ERROR_PERMISSION
+                3 | 4 => StatusCode::FORBIDDEN,
+                // ACK_ERROR_NO_EXIST
+                50 => StatusCode::NOT_FOUND,
+                // ACK_ERROR_UPDATE_ALREADY, ACK_ERROR_EXIST
+                54 | 56 => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Self::ConnectionLost => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Message for the user; MPD's own messages are usually good enough.
+    fn message(&self, err: &anyhow::Error) -> String {
+        match (self, err.downcast_ref::<CommandError>()) {
+            (Self::Mpd(_), Some(CommandError::ErrorResponse { error, .. })) => {
+                format!("MPD: {}", error.message)
+            }
+            (Self::ConnectionLost, _) => "MPD is not connected".to_string(),
+            (Self::Internal, _) => format!("Something went wrong: {err}"),
+            _ => err.to_string(),
+        }
+    }
+}
+
+/// Error returned by the JSON API, rendered as `{"error": "..."}` with a status code derived
+/// from the underlying MPD failure.
+pub struct ApiError(pub anyhow::Error);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let kind = ErrorKind::of(&self.0);
+        if kind.status_code().is_server_error() {
+            tracing::error!("{:#}", self.0);
+        }
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
+            kind.status_code(),
+            Json(serde_json::json!({ "error": self.0.to_string() })),
         )
             .into_response()
     }
 }
 
-impl<E> From<E> for AppError
+impl<E> From<E> for ApiError
 where
     E: Into<anyhow::Error>,
 {
@@ -23,3 +169,101 @@ where
         Self(err.into())
     }
 }
+
+/// Message of an `AppError`, kept with its response for `negotiate_error`.
+#[derive(Clone)]
+struct ErrorMessage(String);
+
+/// Middleware turning errors of UI handlers into `{"error": "..."}` for clients asking for
+/// `application/json`, and adding a toast to errors that don't come from `AppError` (e.g.
+/// rejected query strings).
+pub async fn negotiate_error(request: Request, next: Next) -> Response {
+    let wants_json = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    let response = next.run(request).await;
+
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+
+    let message = match response.extensions().get::<ErrorMessage>() {
+        Some(ErrorMessage(message)) if wants_json => message.clone(),
+        Some(_) => return response,
+        None if response.headers().contains_key("HX-Trigger") => return response,
+        None => {
+            let is_text = response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .is_some_and(|content_type| content_type.as_bytes().starts_with(b"text/plain"));
+            if !is_text {
+                return response;
+            }
+            let (mut parts, body) = response.into_parts();
+            let Ok(body) = axum::body::to_bytes(body, 64 * 1024).await else {
+                return status.into_response();
+            };
+            let message = String::from_utf8_lossy(&body).into_owned();
+            if !wants_json {
+                parts.headers.insert("HX-Trigger", toast_trigger(&message));
+                return Response::from_parts(parts, body.into());
+            }
+            message
+        }
+    };
+
+    (
+        status,
+        [("HX-Trigger", toast_trigger(&message))],
+        Json(serde_json::json!({ "error": message })),
+    )
+        .into_response()
+}
+
+/// `HX-Trigger` header value firing a `showToast` event with the message. Header values are
+/// read as Latin-1 by browsers and may not contain control characters, so everything but
+/// visible ASCII is escaped.
+fn toast_trigger(message: &str) -> HeaderValue {
+    let json = serde_json::json!({ "showToast": { "message": message, "level": "error" } });
+    let mut ascii = String::new();
+    for c in json.to_string().chars() {
+        if c.is_ascii_graphic() || c == ' ' {
+            ascii.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                ascii.push_str(&format!("\\u{unit:04x}"));
+            }
+        }
+    }
+    HeaderValue::from_str(&ascii).unwrap_or_else(|_| {
+        HeaderValue::from_static(
+            r#"{"showToast":{"message":"Something went wrong","level":"error"}}"#,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toast_trigger_escapes_unicode() {
+        let trigger = toast_trigger("Café 🎵");
+        assert_eq!(
+            trigger.to_str().unwrap(),
+            r#"{"showToast":{"level":"error","message":"Caf\u00e9 \ud83c\udfb5"}}"#
+        );
+    }
+
+    #[test]
+    fn toast_trigger_escapes_control_characters() {
+        let trigger = toast_trigger("unknown variant `\u{7f}`\n");
+        assert_eq!(
+            trigger.to_str().unwrap(),
+            r#"{"showToast":{"level":"error","message":"unknown variant `\u007f`\n"}}"#
+        );
+    }
+}
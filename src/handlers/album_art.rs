@@ -1,13 +1,79 @@
-use crate::models::ArtistAlbumQuery;
+use crate::cache::{get_set, Variant};
+use crate::error::AppError;
+use crate::models::CoverQuery;
+use crate::mpd::CoverSource;
 use crate::state::AppState;
-use crate::{cache::get_set, error::AppError};
 use axum::extract::{Query, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderName, StatusCode};
+use axum::response::{IntoResponse, Response};
 use bytes::Bytes;
+use sha1::{Digest, Sha1};
+
+/// Covers rarely change; browsers revalidate with the ETag after a week.
+const COVER_MAX_AGE: &str = "max-age=604800";
 
 pub async fn get_cover(
     State(state): State<AppState>,
-    Query(q): Query<ArtistAlbumQuery>,
-) -> Result<Bytes, AppError> {
-    let cache_key = (q.artist.clone(), q.album.clone());
-    get_set(cache_key, state.album_art_cache, &state.mpd).await
+    Query(q): Query<CoverQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let variant = Variant {
+        size: q.size,
+        format: q.format,
+    };
+    let cache_key = q.key()?;
+    let (cover, source) = get_set(cache_key, variant, state.album_art_cache, &state.mpd).await?;
+    Ok(cover_response(
+        cover,
+        source,
+        state.auth.enabled(),
+        &headers,
+    ))
+}
+
+/// Missing covers are looked for again after `cache::MISSING_TTL`, so browsers revalidate the
+/// fallback image every time. Covers are only kept by shared caches without authentication.
+fn cache_control(source: CoverSource, private: bool) -> String {
+    let visibility = if private { "private" } else { "public" };
+    let age = match source {
+        CoverSource::Fallback => "no-cache",
+        _ => COVER_MAX_AGE,
+    };
+    format!("{visibility}, {age}")
+}
+
+fn cover_response(
+    cover: Bytes,
+    source: CoverSource,
+    private: bool,
+    headers: &HeaderMap,
+) -> Response {
+    let etag = format!("\"{:x}\"", Sha1::digest(&cover));
+    let cache_headers = [
+        (ETAG, etag.clone()),
+        (CACHE_CONTROL, cache_control(source, private)),
+        // where the cover was found, for debugging missing art
+        (
+            HeaderName::from_static("x-cover-source"),
+            source.as_str().to_string(),
+        ),
+    ];
+
+    let not_modified = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    let content_type = image::guess_format(&cover)
+        .map(|format| format.to_mime_type())
+        .unwrap_or("application/octet-stream");
+    (cache_headers, [(CONTENT_TYPE, content_type)], cover).into_response()
 }
@@ -0,0 +1,415 @@
+use crate::cache::{self, CacheStats};
+use crate::error::ApiError;
+use crate::models::{
+    validate_playlist_name, AlbumsQuery, ArtistAlbumQuery, Browse, BrowseQuery, CrossfadeQuery,
+    GenericQuery, MoveQuery, PathQuery, RangeMoveQuery, RenameQuery, ReplayGainQuery, SearchQuery,
+    SeekQuery, SongIdQuery, StationQuery, UrlQuery, VolumeQuery,
+};
+use crate::mpd::{
+    Album, Directory, SearchResults, Song, SongInQueue, Status, StoredPlaylist, StoredPlaylistSong,
+};
+use crate::radio::Station;
+use crate::state::AppState;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct Stats {
+    pub artists: u64,
+    pub albums: u64,
+    pub songs: u64,
+    pub uptime: u64,
+    pub playtime: u64,
+    pub db_playtime: u64,
+    pub db_last_update: u64,
+}
+
+impl From<mpd_client::responses::Stats> for Stats {
+    fn from(stats: mpd_client::responses::Stats) -> Self {
+        Self {
+            artists: stats.artists,
+            albums: stats.albums,
+            songs: stats.songs,
+            uptime: stats.uptime.as_secs(),
+            playtime: stats.playtime.as_secs(),
+            db_playtime: stats.db_playtime.as_secs(),
+            db_last_update: stats.db_last_update,
+        }
+    }
+}
+
+pub async fn get_artists(
+    State(state): State<AppState>,
+    Query(q): Query<GenericQuery>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let mut artists = state
+        .mpd
+        .get_tag_values(mpd_client::tag::Tag::Artist, &q.q)
+        .await?;
+    artists.sort_by_key(|a| a.to_lowercase());
+    Ok(Json(artists))
+}
+
+pub async fn get_albums(
+    State(state): State<AppState>,
+    Query(q): Query<AlbumsQuery>,
+) -> Result<Json<Vec<Album>>, ApiError> {
+    let artist = (q.by == Browse::Artist).then_some(q.value.as_str());
+    Ok(Json(
+        state
+            .mpd
+            .get_albums(q.by.filter(&q.value), q.by.artist_tag(), artist)
+            .await?,
+    ))
+}
+
+pub async fn browse(
+    State(state): State<AppState>,
+    Query(q): Query<BrowseQuery>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let mut values =
+        q.by.entries(state.mpd.get_tag_values(q.by.tag(), &q.q).await?);
+    values.sort_by_key(|v| v.to_lowercase());
+    Ok(Json(values))
+}
+
+pub async fn get_songs(
+    State(state): State<AppState>,
+    Query(q): Query<ArtistAlbumQuery>,
+) -> Result<Json<Vec<Song>>, ApiError> {
+    Ok(Json(
+        state
+            .mpd
+            .get_songs(q.artist_tag, &q.artist, &q.album)
+            .await?,
+    ))
+}
+
+pub async fn search(
+    State(state): State<AppState>,
+    Query(q): Query<SearchQuery>,
+) -> Result<Json<SearchResults>, ApiError> {
+    let query = q.q.unwrap_or_default();
+    Ok(Json(state.mpd.search(&query, q.tag.tags()).await?))
+}
+
+pub async fn list_directory(
+    State(state): State<AppState>,
+    Query(q): Query<PathQuery>,
+) -> Result<Json<Directory>, ApiError> {
+    Ok(Json(state.mpd.list_directory(&q.path).await?))
+}
+
+pub async fn get_stations(State(state): State<AppState>) -> Json<Vec<Station>> {
+    Json(state.stations.lock().await.list())
+}
+
+pub async fn add_station(
+    State(state): State<AppState>,
+    Query(q): Query<StationQuery>,
+) -> Result<StatusCode, ApiError> {
+    state.stations.lock().await.add(&q.name, &q.url).await?;
+    Ok(StatusCode::CREATED)
+}
+
+pub async fn remove_station(
+    State(state): State<AppState>,
+    Query(q): Query<UrlQuery>,
+) -> Result<StatusCode, ApiError> {
+    state.stations.lock().await.remove(&q.url).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_queue(State(state): State<AppState>) -> Result<Json<Vec<SongInQueue>>, ApiError> {
+    Ok(Json(state.mpd.get_playlist().await?))
+}
+
+pub async fn clear_queue(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.clear_playlist().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn remove_from_queue(
+    State(state): State<AppState>,
+    Path(song_id): Path<u64>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.remove_from_playlist(song_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn move_in_queue(
+    State(state): State<AppState>,
+    Path(song_id): Path<u64>,
+    Query(q): Query<MoveQuery>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.move_song(song_id, q.to).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn play_next(
+    State(state): State<AppState>,
+    Path(song_id): Path<u64>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.play_next(song_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn move_range_in_queue(
+    State(state): State<AppState>,
+    Query(q): Query<RangeMoveQuery>,
+) -> Result<StatusCode, ApiError> {
+    q.validate()?;
+    state.mpd.move_range(q.start, q.end, q.to).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn append_album(
+    State(state): State<AppState>,
+    Query(q): Query<ArtistAlbumQuery>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .mpd
+        .append_album_to_playlist(q.artist_tag, &q.artist, &q.album)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn append_song(
+    State(state): State<AppState>,
+    Query(q): Query<UrlQuery>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.append_song_by_url(&q.url).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn play_album(
+    State(state): State<AppState>,
+    Query(q): Query<ArtistAlbumQuery>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .mpd
+        .play_album(q.artist_tag, &q.artist, &q.album)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn play_song(
+    State(state): State<AppState>,
+    Query(q): Query<UrlQuery>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.play_song_by_url(&q.url).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_stored_playlists(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<StoredPlaylist>>, ApiError> {
+    Ok(Json(state.mpd.get_stored_playlists().await?))
+}
+
+pub async fn get_stored_playlist(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<StoredPlaylistSong>>, ApiError> {
+    Ok(Json(state.mpd.get_stored_playlist(&name).await?))
+}
+
+pub async fn save_queue_as_playlist(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    validate_playlist_name(&name)?;
+    state.mpd.save_queue_as_playlist(&name).await?;
+    Ok(StatusCode::CREATED)
+}
+
+pub async fn delete_playlist(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.delete_playlist(&name).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn load_playlist(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.load_playlist(&name).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn play_playlist(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.play_playlist(&name).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn rename_playlist(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(q): Query<RenameQuery>,
+) -> Result<StatusCode, ApiError> {
+    validate_playlist_name(&q.to)?;
+    state.mpd.rename_playlist(&name, &q.to).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn add_song_to_playlist(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(q): Query<UrlQuery>,
+) -> Result<StatusCode, ApiError> {
+    validate_playlist_name(&name)?;
+    state.mpd.add_song_to_playlist(&name, &q.url).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn add_album_to_playlist(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(q): Query<ArtistAlbumQuery>,
+) -> Result<StatusCode, ApiError> {
+    validate_playlist_name(&name)?;
+    state
+        .mpd
+        .add_album_to_playlist(&name, q.artist_tag, &q.artist, &q.album)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn remove_from_playlist(
+    State(state): State<AppState>,
+    Path((name, position)): Path<(String, usize)>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .mpd
+        .remove_from_stored_playlist(&name, position)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_status(State(state): State<AppState>) -> Result<Json<Status>, ApiError> {
+    Ok(Json(state.mpd.get_status().await?))
+}
+
+#[derive(Serialize)]
+pub struct Volume {
+    pub volume: u8,
+}
+
+pub async fn get_volume(State(state): State<AppState>) -> Result<Json<Volume>, ApiError> {
+    let volume = state.mpd.get_status().await?.volume;
+    Ok(Json(Volume { volume }))
+}
+
+pub async fn get_stats(State(state): State<AppState>) -> Result<Json<Stats>, ApiError> {
+    Ok(Json(state.mpd.stats().await?.into()))
+}
+
+pub async fn get_cache(State(state): State<AppState>) -> Json<CacheStats> {
+    Json(state.album_art_cache.lock().await.stats())
+}
+
+pub async fn purge_cache(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    cache::purge(&state.album_art_cache).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn update_db(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.update_db().await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn control_play(
+    State(state): State<AppState>,
+    Query(q): Query<SongIdQuery>,
+) -> Result<StatusCode, ApiError> {
+    if let Some(song_id) = q.song_id {
+        state.mpd.play_song(song_id).await?;
+    } else {
+        state.mpd.play().await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn control_pause(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.pause(true).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn control_unpause(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.pause(false).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn control_prev(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.prev().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn control_next(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.next().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn control_seek(
+    State(state): State<AppState>,
+    Query(q): Query<SeekQuery>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.seek(q.seek_mode()?).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn toggle_repeat(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.toggle_repeat().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn toggle_random(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.toggle_random().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn control_volume(
+    State(state): State<AppState>,
+    Query(q): Query<VolumeQuery>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.set_volume(q.volume_change()?).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn toggle_mute(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.toggle_mute().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn cycle_single(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.cycle_single().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn toggle_consume(State(state): State<AppState>) -> Result<StatusCode, ApiError> {
+    state.mpd.toggle_consume().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn control_crossfade(
+    State(state): State<AppState>,
+    Query(q): Query<CrossfadeQuery>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.set_crossfade(q.seconds).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn control_replay_gain(
+    State(state): State<AppState>,
+    Query(q): Query<ReplayGainQuery>,
+) -> Result<StatusCode, ApiError> {
+    state.mpd.set_replay_gain_mode(q.mode.into()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
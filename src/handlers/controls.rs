@@ -1,5 +1,5 @@
 use crate::error::AppError;
-use crate::models::SongIdQuery;
+use crate::models::{CrossfadeQuery, ReplayGainQuery, SeekQuery, SongIdQuery, VolumeQuery};
 use crate::state::AppState;
 use axum::extract::{Query, State};
 
@@ -35,6 +35,14 @@ pub async fn control_next(State(state): State<AppState>) -> Result<(), AppError>
     Ok(())
 }
 
+pub async fn control_seek(
+    State(state): State<AppState>,
+    Query(q): Query<SeekQuery>,
+) -> Result<(), AppError> {
+    state.mpd.seek(q.seek_mode()?).await?;
+    Ok(())
+}
+
 pub async fn toggle_random(State(state): State<AppState>) -> Result<(), AppError> {
     state.mpd.toggle_random().await?;
     Ok(())
@@ -44,3 +52,42 @@ pub async fn toggle_repeat(State(state): State<AppState>) -> Result<(), AppError
     state.mpd.toggle_repeat().await?;
     Ok(())
 }
+
+pub async fn cycle_single(State(state): State<AppState>) -> Result<(), AppError> {
+    state.mpd.cycle_single().await?;
+    Ok(())
+}
+
+pub async fn toggle_consume(State(state): State<AppState>) -> Result<(), AppError> {
+    state.mpd.toggle_consume().await?;
+    Ok(())
+}
+
+pub async fn control_crossfade(
+    State(state): State<AppState>,
+    Query(q): Query<CrossfadeQuery>,
+) -> Result<(), AppError> {
+    state.mpd.set_crossfade(q.seconds).await?;
+    Ok(())
+}
+
+pub async fn control_replay_gain(
+    State(state): State<AppState>,
+    Query(q): Query<ReplayGainQuery>,
+) -> Result<(), AppError> {
+    state.mpd.set_replay_gain_mode(q.mode.into()).await?;
+    Ok(())
+}
+
+pub async fn control_volume(
+    State(state): State<AppState>,
+    Query(q): Query<VolumeQuery>,
+) -> Result<(), AppError> {
+    state.mpd.set_volume(q.volume_change()?).await?;
+    Ok(())
+}
+
+pub async fn toggle_mute(State(state): State<AppState>) -> Result<(), AppError> {
+    state.mpd.toggle_mute().await?;
+    Ok(())
+}
@@ -1,6 +1,6 @@
+use crate::cache;
 use crate::error::AppError;
-use crate::handlers::library::render_index;
-use crate::mpd;
+use crate::handlers::library::{page_contents, render_index};
 use crate::state::AppState;
 use crate::templates as t;
 use axum::extract::State;
@@ -11,27 +11,32 @@ pub async fn get_database(
     State(state): State<AppState>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
-    let stats = state.mpd.stats().await?;
+    let stats = state.mpd.stats().await.map(Some);
     let tabs = t::TabsTemplate {
         database_active: true,
-        ..Default::default()
+        ..state.tabs()
     };
-    let mpd_addr = mpd::mpd_addr();
+    let mpd_addr = state.server.mpd_addr();
+    let cache = state.album_art_cache.lock().await.stats();
 
     if headers.contains_key("HX-Request") {
+        let stats = stats?;
         Ok(t::DatabaseTemplate {
             tabs: Some(tabs),
             mpd_addr,
             stats,
+            cache,
         }
         .into_response())
     } else {
+        let stats = page_contents(&state, stats).await?;
         let index = render_index(
-            &state.mpd,
+            &state,
             t::Page::Database(t::DatabaseTemplate {
                 tabs: None,
                 mpd_addr,
                 stats,
+                cache,
             }),
             tabs,
         )
@@ -46,7 +51,18 @@ pub async fn update_db(State(state): State<AppState>) -> Result<(), AppError> {
 }
 
 pub async fn update_status(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
-    let updating = state.mpd.get_status().await?.ubdating_db;
+    let snapshot = state.snapshot.borrow().clone();
+    let updating = match snapshot {
+        Some(snapshot) => snapshot.status.ubdating_db,
+        None => state.mpd.get_status().await?.ubdating_db,
+    };
     let template = t::DatabaseUpdateStatusTemplate { updating };
     Ok(template)
 }
+
+pub async fn purge_cache(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    cache::purge(&state.album_art_cache).await?;
+    Ok(t::DatabaseCacheTemplate {
+        cache: state.album_art_cache.lock().await.stats(),
+    })
+}
@@ -0,0 +1,41 @@
+use crate::error::AppError;
+use crate::handlers::library::{page_contents, render_index};
+use crate::models::PathQuery;
+use crate::state::AppState;
+use crate::templates as t;
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+
+pub async fn get_files(
+    State(state): State<AppState>,
+    Query(q): Query<PathQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let directory = state.mpd.list_directory(&q.path).await;
+    let tabs = t::TabsTemplate {
+        files_active: true,
+        ..state.tabs()
+    };
+
+    if headers.contains_key("HX-Request") {
+        let directory = directory?;
+        Ok(t::FilesTemplate {
+            tabs: Some(tabs),
+            directory,
+        }
+        .into_response())
+    } else {
+        let directory = page_contents(&state, directory).await?;
+        let index = render_index(
+            &state,
+            t::Page::Files(t::FilesTemplate {
+                tabs: None,
+                directory,
+            }),
+            tabs,
+        )
+        .await?;
+        Ok(index.into_response())
+    }
+}
@@ -1,31 +1,38 @@
+use crate::connection::ConnectionState;
 use crate::error::AppError;
-use crate::models::{ArtistAlbumQuery, ArtistQuery, GenericQuery};
-use crate::mpd::Mpd;
+use crate::models::{AlbumsQuery, ArtistAlbumQuery, Browse, BrowseQuery};
 use crate::state::AppState;
 use crate::templates as t;
 use axum::extract::{Query, State};
 use axum::http::HeaderMap;
 use axum::response::IntoResponse;
+use mpd_client::client::CommandError;
 
 pub async fn get_library(
     State(state): State<AppState>,
-    Query(artists_search_query): Query<GenericQuery>,
+    Query(browse_query): Query<BrowseQuery>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
     let tabs = t::TabsTemplate {
         library_active: true,
-        ..Default::default()
+        ..state.tabs()
     };
 
-    let query = artists_search_query.q;
-    let artists = state.mpd.get_artists(&query).await?;
+    let BrowseQuery { by, q: query } = browse_query;
 
     if headers.contains_key("HX-Request") {
-        Ok(t::LibraryTemplate::new(Some(tabs), artists, query).into_response())
+        let values = by.entries(state.mpd.get_tag_values(by.tag(), &query).await?);
+        Ok(t::LibraryTemplate::new(Some(tabs), values, query, by).into_response())
     } else {
+        // on connection or permission errors, render_index shows the error page instead
+        let values = state
+            .mpd
+            .get_tag_values(by.tag(), &query)
+            .await
+            .unwrap_or_default();
         let index = render_index(
-            &state.mpd,
-            t::Page::Library(t::LibraryTemplate::new(None, artists, query)),
+            &state,
+            t::Page::Library(t::LibraryTemplate::new(None, by.entries(values), query, by)),
             tabs,
         )
         .await?;
@@ -35,28 +42,36 @@ pub async fn get_library(
 
 pub async fn get_albums(
     State(state): State<AppState>,
-    Query(query): Query<ArtistQuery>,
+    Query(q): Query<AlbumsQuery>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
-    let albums = state.mpd.get_albums(&query.artist).await?;
+    let artist = (q.by == Browse::Artist).then_some(q.value.as_str());
+    let albums = state
+        .mpd
+        .get_albums(q.by.filter(&q.value), q.by.artist_tag(), artist)
+        .await;
     let tabs = t::TabsTemplate {
         library_active: true,
-        ..Default::default()
+        ..state.tabs()
     };
 
     if headers.contains_key("HX-Request") {
+        let albums = albums?;
         Ok(t::AlbumsTemplate {
             tabs: Some(tabs),
-            artist: query.artist,
+            title: q.value,
+            by: q.by,
             albums,
         }
         .into_response())
     } else {
+        let albums = page_contents(&state, albums).await?;
         let index = render_index(
-            &state.mpd,
+            &state,
             t::Page::Albums(t::AlbumsTemplate {
                 tabs: None,
-                artist: query.artist,
+                title: q.value,
+                by: q.by,
                 albums,
             }),
             tabs,
@@ -71,26 +86,30 @@ pub async fn get_songs(
     Query(q): Query<ArtistAlbumQuery>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
-    let songs = state.mpd.get_songs(&q.artist, &q.album).await?;
+    let songs = state.mpd.get_songs(q.artist_tag, &q.artist, &q.album).await;
     let tabs = t::TabsTemplate {
         library_active: true,
-        ..Default::default()
+        ..state.tabs()
     };
 
     if headers.contains_key("HX-Request") {
+        let songs = songs?;
         Ok(t::AlbumSongsTemplate {
             tabs: Some(tabs),
             artist: q.artist,
+            artist_tag: q.artist_tag,
             album: q.album,
             songs,
         }
         .into_response())
     } else {
+        let songs = page_contents(&state, songs).await?;
         let index = render_index(
-            &state.mpd,
+            &state,
             t::Page::Songs(t::AlbumSongsTemplate {
                 tabs: None,
                 artist: q.artist,
+                artist_tag: q.artist_tag,
                 album: q.album,
                 songs,
             }),
@@ -102,18 +121,57 @@ pub async fn get_songs(
 }
 
 pub async fn render_index(
-    mpd: &Mpd,
+    state: &AppState,
     page: t::Page,
     tabs: t::TabsTemplate,
 ) -> Result<impl IntoResponse, AppError> {
-    let error = mpd.stats().await.err().map(|e| e.to_string());
-    Ok(t::IndexTemplate { error, page, tabs })
+    let connection = state.connection.borrow().clone();
+    let disconnected = !connection.is_connected();
+    let error = match connection {
+        ConnectionState::Connected => state.mpd.stats().await.err().map(describe_error),
+        ConnectionState::Connecting => Some("Connecting to MPD...".to_string()),
+        ConnectionState::Reconnecting { error, .. } => Some(error),
+    };
+    Ok(t::IndexTemplate {
+        error,
+        disconnected,
+        csrf_token: state.csrf_token.as_str().to_string(),
+        page,
+        tabs,
+        prefix: state.prefix.clone(),
+    })
+}
+
+/// Contents of a full page load. When MPD can't be used at all, `render_index` shows why (and
+/// reloads once it's back) instead of the page, so the contents are left empty; other errors,
+/// like unknown albums, are returned.
+pub async fn page_contents<T: Default>(
+    state: &AppState,
+    contents: anyhow::Result<T>,
+) -> Result<T, AppError> {
+    match contents {
+        Ok(contents) => Ok(contents),
+        Err(_) if state.mpd.stats().await.is_err() => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn describe_error(error: anyhow::Error) -> String {
+    match error.downcast_ref::<CommandError>() {
+        Some(CommandError::ErrorResponse { error, .. }) if matches!(error.code, 3 | 4) => {
+            format!(
+                "MPD refused access ({}), set the password with MPD_HOST=password@host",
+                error.message
+            )
+        }
+        _ => error.to_string(),
+    }
 }
 
 pub async fn get_index(
     state: State<AppState>,
-    artists_search_query: Query<GenericQuery>,
+    browse_query: Query<BrowseQuery>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
-    get_library(state, artists_search_query, headers).await
+    get_library(state, browse_query, headers).await
 }
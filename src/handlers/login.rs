@@ -0,0 +1,47 @@
+use crate::auth::{Auth, Role};
+use crate::csrf::CsrfToken;
+use crate::error::AppError;
+use crate::models::LoginForm;
+use crate::templates as t;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+
+/// The role was resolved by `auth::authenticate` already.
+pub async fn get_login(
+    State(auth): State<Auth>,
+    Extension(role): Extension<Role>,
+    Extension(csrf_token): Extension<CsrfToken>,
+) -> impl IntoResponse {
+    t::LoginTemplate {
+        role,
+        guest_access: auth.guest_access(),
+        error: None,
+        csrf_token: csrf_token.as_str().to_string(),
+    }
+}
+
+pub async fn login(
+    State(auth): State<Auth>,
+    Extension(csrf_token): Extension<CsrfToken>,
+    Form(form): Form<LoginForm>,
+) -> Result<impl IntoResponse, AppError> {
+    match auth.login(&form.password).await? {
+        Some(cookie) => Ok(([(header::SET_COOKIE, cookie)], Redirect::to("/")).into_response()),
+        None => {
+            let template = t::LoginTemplate {
+                role: Role::Guest,
+                guest_access: auth.guest_access(),
+                error: Some("Wrong password".to_string()),
+                csrf_token: csrf_token.as_str().to_string(),
+            };
+            Ok((StatusCode::UNAUTHORIZED, template).into_response())
+        }
+    }
+}
+
+pub async fn logout(State(auth): State<Auth>, headers: HeaderMap) -> impl IntoResponse {
+    let cookie = auth.logout(&headers).await;
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/login"))
+}
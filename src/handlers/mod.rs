@@ -1,7 +1,14 @@
 pub mod album_art;
+pub mod api;
 pub mod controls;
 pub mod database;
+pub mod files;
 pub mod library;
+pub mod login;
 pub mod now_playing;
+pub mod party;
 pub mod playlist;
+pub mod radio;
+pub mod search;
 pub mod status;
+pub mod stored_playlists;
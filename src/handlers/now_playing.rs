@@ -1,6 +1,6 @@
 use crate::error::AppError;
 use crate::handlers::library::render_index;
-use crate::mpd::Mpd;
+use crate::snapshot::{unix_millis, Snapshot};
 use crate::state::AppState;
 use crate::templates as t;
 use askama::Template;
@@ -15,15 +15,22 @@ pub async fn get_now_playing(
 ) -> Result<impl IntoResponse, AppError> {
     let tabs = t::TabsTemplate {
         now_playing_active: true,
-        ..Default::default()
+        ..state.tabs()
     };
 
     if headers.contains_key("HX-Request") {
-        Ok(t::NowPlayingTemplate { tabs: Some(tabs) }.into_response())
+        Ok(t::NowPlayingTemplate {
+            tabs: Some(tabs),
+            prefix: state.prefix,
+        }
+        .into_response())
     } else {
         let index = render_index(
-            &state.mpd,
-            t::Page::NowPlaying(t::NowPlayingTemplate { tabs: None }),
+            &state,
+            t::Page::NowPlaying(t::NowPlayingTemplate {
+                tabs: None,
+                prefix: state.prefix.clone(),
+            }),
             tabs,
         )
         .await?;
@@ -39,19 +46,34 @@ pub async fn get_now_playing_content(
 }
 
 async fn handle_ws_now_playing(state: AppState, mut socket: WebSocket) {
-    let mpd = state.mpd;
+    let mut snapshot_rx = state.snapshot;
 
     loop {
-        if send_now_playing_content(&mpd, &mut socket).await.is_err() {
+        let snapshot = snapshot_rx.borrow_and_update().clone();
+        if let Some(snapshot) = snapshot {
+            if send_now_playing_content(&snapshot, &mut socket)
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        if snapshot_rx.changed().await.is_err() {
             return;
         }
-        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
     }
 }
 
-async fn send_now_playing_content(mpd: &Mpd, socket: &mut WebSocket) -> anyhow::Result<()> {
-    let status = mpd.get_status().await?;
-    let template = t::NowPlayingContentTemplate { status }.render()?;
+async fn send_now_playing_content(
+    snapshot: &Snapshot,
+    socket: &mut WebSocket,
+) -> anyhow::Result<()> {
+    let template = t::NowPlayingContentTemplate {
+        status: &snapshot.status,
+        timestamp: snapshot.timestamp,
+        now: unix_millis(),
+    }
+    .render()?;
     socket.send(template.into()).await?;
 
     Ok(())
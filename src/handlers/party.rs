@@ -0,0 +1,191 @@
+use crate::auth::{cookie, Role};
+use crate::csrf::{constant_time_eq, random_token};
+use crate::error::AppError;
+use crate::handlers::library::render_index;
+use crate::models::{PartyRequestQuery, PartyVoteQuery, SearchQuery, UrlQuery};
+use crate::state::AppState;
+use crate::templates as t;
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts, Query, State};
+use axum::http::header::InvalidHeaderValue;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::IntoResponse;
+use axum::Extension;
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+const CLIENT_COOKIE: &str = "rump_client";
+const CLIENT_COOKIE_LIFETIME: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// The browser making a request, which is how guests are told apart for votes and their own
+/// requests. Identified by a random ID in a cookie that the party page hands out, so that guests
+/// behind the same address (a reverse proxy, a shared network) count separately; browsers
+/// without the cookie are identified by IP address. The rate limit is per IP address, as new IDs
+/// are only a reload away.
+pub struct Client {
+    id: String,
+    ip: IpAddr,
+    has_cookie: bool,
+}
+
+impl Client {
+    /// `Set-Cookie` header handing out `id`, signed so that IDs can't be made up.
+    fn cookie(state: &AppState, id: &str) -> Result<HeaderValue, InvalidHeaderValue> {
+        HeaderValue::from_str(&format!(
+            "{CLIENT_COOKIE}={id}.{}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+            state.csrf_token.sign(id),
+            CLIENT_COOKIE_LIFETIME.as_secs()
+        ))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(addr)| {
+                addr.ip()
+            });
+        let signed_id = cookie(&parts.headers, CLIENT_COOKIE)
+            .and_then(|value| value.split_once('.'))
+            .filter(|(id, signature)| {
+                constant_time_eq(signature.as_bytes(), state.csrf_token.sign(id).as_bytes())
+            });
+        Ok(match signed_id {
+            Some((id, _)) => Self {
+                id: id.to_string(),
+                ip,
+                has_cookie: true,
+            },
+            None => Self {
+                id: format!("ip:{ip}"),
+                ip,
+                has_cookie: false,
+            },
+        })
+    }
+}
+
+pub async fn get_party(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    mut client: Client,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let tabs = t::TabsTemplate {
+        party_active: true,
+        ..state.tabs()
+    };
+    let set_cookie = if client.has_cookie {
+        None
+    } else {
+        client.id = random_token()?;
+        Some(Client::cookie(&state, &client.id)?)
+    };
+    let requests = requests(&state, role, &client).await;
+
+    let mut response = if headers.contains_key("HX-Request") {
+        t::PartyTemplate {
+            tabs: Some(tabs),
+            requests,
+        }
+        .into_response()
+    } else {
+        render_index(
+            &state,
+            t::Page::Party(t::PartyTemplate {
+                tabs: None,
+                requests,
+            }),
+            tabs,
+        )
+        .await?
+        .into_response()
+    };
+    if let Some(set_cookie) = set_cookie {
+        response
+            .headers_mut()
+            .insert(header::SET_COOKIE, set_cookie);
+    }
+    Ok(response)
+}
+
+/// Songs only, guests request one at a time.
+pub async fn search_songs(
+    State(state): State<AppState>,
+    Query(q): Query<SearchQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = q.q.unwrap_or_default();
+    let results = state.mpd.search(&query, q.tag.tags()).await?;
+    Ok(t::PartySearchTemplate { query, results })
+}
+
+pub async fn get_requests(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    client: Client,
+) -> impl IntoResponse {
+    requests(&state, role, &client).await
+}
+
+/// Only songs of the music database can be requested.
+pub async fn request_song(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    client: Client,
+    Query(q): Query<UrlQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let song = state.mpd.get_song(&q.url).await?;
+    state
+        .party
+        .request(&client.id, client.ip, role, song)
+        .await?;
+    Ok(requests(&state, role, &client).await)
+}
+
+pub async fn vote(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    client: Client,
+    Query(q): Query<PartyVoteQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    state.party.vote(&client.id, role, q.id, q.vote).await?;
+    Ok(requests(&state, role, &client).await)
+}
+
+pub async fn approve(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    client: Client,
+    Query(q): Query<PartyRequestQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    state.party.approve(q.id).await?;
+    Ok(requests(&state, role, &client).await)
+}
+
+pub async fn remove(
+    State(state): State<AppState>,
+    Extension(role): Extension<Role>,
+    client: Client,
+    Query(q): Query<PartyRequestQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    state.party.remove(q.id).await?;
+    Ok(requests(&state, role, &client).await)
+}
+
+async fn requests(state: &AppState, role: Role, client: &Client) -> t::PartyRequestsTemplate {
+    t::PartyRequestsTemplate {
+        requests: state.party.list(&client.id, role).await,
+        admin: role == Role::Admin,
+        approval: state.party.needs_approval(),
+    }
+}
@@ -1,7 +1,7 @@
 use crate::error::AppError;
 use crate::handlers::library::render_index;
-use crate::models::{ArtistAlbumQuery, SongIdQuery, UrlQuery};
-use crate::mpd::Mpd;
+use crate::models::{ArtistAlbumQuery, RangeMoveQuery, SongIdQuery, SongMoveQuery, UrlQuery};
+use crate::snapshot::Snapshot;
 use crate::state::AppState;
 use crate::templates as t;
 use askama::Template;
@@ -9,7 +9,6 @@ use axum::extract::ws::{WebSocket, WebSocketUpgrade};
 use axum::extract::{Query, State};
 use axum::http::HeaderMap;
 use axum::response::IntoResponse;
-use mpd_client::client::Subsystem;
 
 pub async fn get_playlist_songs(
     State(state): State<AppState>,
@@ -18,12 +17,10 @@ pub async fn get_playlist_songs(
     ws.on_upgrade(|socket| handle_ws_playlist(state, socket))
 }
 
-async fn send_playlist(mpd: &Mpd, socket: &mut WebSocket) -> anyhow::Result<()> {
-    let status = mpd.get_status().await?;
-    let playlist = mpd.get_playlist().await?;
+async fn send_playlist(snapshot: &Snapshot, socket: &mut WebSocket) -> anyhow::Result<()> {
     let template = t::PlaylistSongsTemplate {
-        songs: playlist,
-        status,
+        songs: &snapshot.queue,
+        status: &snapshot.status,
     }
     .render()?;
     socket.send(template.into()).await?;
@@ -32,26 +29,17 @@ async fn send_playlist(mpd: &Mpd, socket: &mut WebSocket) -> anyhow::Result<()>
 }
 
 async fn handle_ws_playlist(state: AppState, mut socket: WebSocket) {
-    let mpd = state.mpd;
-    let mut rx = state.event_tx.subscribe();
+    let mut snapshot_rx = state.snapshot;
 
-    if send_playlist(&mpd, &mut socket).await.is_err() {
-        return;
-    }
     loop {
-        let event = rx.recv().await;
-        if event.is_err() {
-            return;
-        }
-        let event = event.unwrap();
-
-        match event {
-            Subsystem::Player | Subsystem::Queue | Subsystem::Options
-                if send_playlist(&mpd, &mut socket).await.is_err() =>
-            {
+        let snapshot = snapshot_rx.borrow_and_update().clone();
+        if let Some(snapshot) = snapshot {
+            if send_playlist(&snapshot, &mut socket).await.is_err() {
                 return;
             }
-            _ => {}
+        }
+        if snapshot_rx.changed().await.is_err() {
+            return;
         }
     }
 }
@@ -62,15 +50,22 @@ pub async fn get_playlist(
 ) -> Result<impl IntoResponse, AppError> {
     let tabs = t::TabsTemplate {
         playlist_active: true,
-        ..Default::default()
+        ..state.tabs()
     };
 
     if headers.contains_key("HX-Request") {
-        Ok(t::PlaylistTemplate { tabs: Some(tabs) }.into_response())
+        Ok(t::PlaylistTemplate {
+            tabs: Some(tabs),
+            prefix: state.prefix,
+        }
+        .into_response())
     } else {
         let index = render_index(
-            &state.mpd,
-            t::Page::Playlist(t::PlaylistTemplate { tabs: None }),
+            &state,
+            t::Page::Playlist(t::PlaylistTemplate {
+                tabs: None,
+                prefix: state.prefix.clone(),
+            }),
             tabs,
         )
         .await?;
@@ -89,7 +84,7 @@ pub async fn append_album(
 ) -> Result<(), AppError> {
     state
         .mpd
-        .append_album_to_playlist(&q.artist, &q.album)
+        .append_album_to_playlist(q.artist_tag, &q.artist, &q.album)
         .await?;
     Ok(())
 }
@@ -98,7 +93,10 @@ pub async fn play_album(
     State(state): State<AppState>,
     Query(q): Query<ArtistAlbumQuery>,
 ) -> Result<(), AppError> {
-    state.mpd.play_album(&q.artist, &q.album).await?;
+    state
+        .mpd
+        .play_album(q.artist_tag, &q.artist, &q.album)
+        .await?;
     Ok(())
 }
 
@@ -127,3 +125,30 @@ pub async fn remove_song_by_id(
     }
     Ok(())
 }
+
+pub async fn move_song(
+    State(state): State<AppState>,
+    Query(q): Query<SongMoveQuery>,
+) -> Result<(), AppError> {
+    state.mpd.move_song(q.song_id, q.to).await?;
+    Ok(())
+}
+
+pub async fn move_range(
+    State(state): State<AppState>,
+    Query(q): Query<RangeMoveQuery>,
+) -> Result<(), AppError> {
+    q.validate()?;
+    state.mpd.move_range(q.start, q.end, q.to).await?;
+    Ok(())
+}
+
+pub async fn play_next(
+    State(state): State<AppState>,
+    Query(q): Query<SongIdQuery>,
+) -> Result<(), AppError> {
+    if let Some(song_id) = q.song_id {
+        state.mpd.play_next(song_id).await?;
+    }
+    Ok(())
+}
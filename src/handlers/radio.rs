@@ -0,0 +1,60 @@
+use crate::error::AppError;
+use crate::handlers::library::render_index;
+use crate::models::{StationQuery, UrlQuery};
+use crate::state::AppState;
+use crate::templates as t;
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+
+pub async fn get_radio(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let stations = state.stations.lock().await.list();
+    let tabs = t::TabsTemplate {
+        radio_active: true,
+        ..state.tabs()
+    };
+
+    if headers.contains_key("HX-Request") {
+        Ok(t::RadioTemplate {
+            tabs: Some(tabs),
+            stations,
+        }
+        .into_response())
+    } else {
+        let index = render_index(
+            &state,
+            t::Page::Radio(t::RadioTemplate {
+                tabs: None,
+                stations,
+            }),
+            tabs,
+        )
+        .await?;
+        Ok(index.into_response())
+    }
+}
+
+pub async fn add_station(
+    State(state): State<AppState>,
+    Query(q): Query<StationQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut stations = state.stations.lock().await;
+    stations.add(&q.name, &q.url).await?;
+    Ok(t::RadioStationsTemplate {
+        stations: stations.list(),
+    })
+}
+
+pub async fn remove_station(
+    State(state): State<AppState>,
+    Query(q): Query<UrlQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut stations = state.stations.lock().await;
+    stations.remove(&q.url).await?;
+    Ok(t::RadioStationsTemplate {
+        stations: stations.list(),
+    })
+}
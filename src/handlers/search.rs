@@ -0,0 +1,50 @@
+use crate::error::AppError;
+use crate::handlers::library::render_index;
+use crate::models::SearchQuery;
+use crate::state::AppState;
+use crate::templates as t;
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+
+pub async fn get_search(
+    State(state): State<AppState>,
+    Query(q): Query<SearchQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let tabs = t::TabsTemplate {
+        search_active: true,
+        ..state.tabs()
+    };
+    let query = q.q.unwrap_or_default();
+
+    if headers.contains_key("HX-Request") {
+        let results = state.mpd.search(&query, q.tag.tags()).await?;
+        Ok(t::SearchTemplate {
+            tabs: Some(tabs),
+            query,
+            tag: q.tag,
+            results,
+        }
+        .into_response())
+    } else {
+        // on connection or permission errors, render_index shows the error page instead
+        let results = state
+            .mpd
+            .search(&query, q.tag.tags())
+            .await
+            .unwrap_or_default();
+        let index = render_index(
+            &state,
+            t::Page::Search(t::SearchTemplate {
+                tabs: None,
+                query,
+                tag: q.tag,
+                results,
+            }),
+            tabs,
+        )
+        .await?;
+        Ok(index.into_response())
+    }
+}
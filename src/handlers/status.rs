@@ -1,12 +1,12 @@
-use crate::cache::{get_set, AlbumArtCache};
-use crate::mpd::Mpd;
+use crate::cache::{get_set, AlbumArtCache, CoverKey, Variant};
+use crate::connection::ConnectionState;
+use crate::mpd::{Mpd, Status};
 use crate::state::AppState;
 use crate::templates as t;
 use askama::Template;
 use axum::extract::ws::{WebSocket, WebSocketUpgrade};
 use axum::extract::State;
 use axum::response::IntoResponse;
-use mpd_client::client::Subsystem;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -15,57 +15,72 @@ pub async fn get_status(State(state): State<AppState>, ws: WebSocketUpgrade) ->
 }
 
 async fn handle_ws_status(state: AppState, mut socket: WebSocket) {
-    let mut mpd = state.mpd;
-    let mut rx = state.event_tx.subscribe();
+    let mut snapshot_rx = state.snapshot;
+    let mut connection_rx = state.connection;
+    let mut last_background: Option<(Option<CoverKey>, t::Gradient)> = None;
 
-    let mut last_background: Option<(Option<(String, String)>, t::Gradient)> = None;
-
-    if send_mpd_status(
-        &mut mpd,
-        state.album_art_cache.clone(),
-        &mut socket,
-        &mut last_background,
-    )
-    .await
-    .is_err()
+    let connection = connection_rx.borrow_and_update().clone();
+    if send_connection_state(&connection, &mut socket)
+        .await
+        .is_err()
     {
         return;
     }
 
     loop {
-        let event = rx.recv().await;
-        if event.is_err() {
-            return;
-        }
-        let event = event.unwrap();
-
-        match event {
-            Subsystem::Player | Subsystem::Queue
-                if send_mpd_status(
-                    &mut mpd,
-                    state.album_art_cache.clone(),
-                    &mut socket,
-                    &mut last_background,
-                )
-                .await
-                .is_err() =>
+        let snapshot = snapshot_rx.borrow_and_update().clone();
+        if let Some(snapshot) = snapshot {
+            if send_mpd_status(
+                &snapshot.status,
+                &state.mpd,
+                state.album_art_cache.clone(),
+                &mut socket,
+                &mut last_background,
+            )
+            .await
+            .is_err()
             {
                 return;
             }
-            _ => {}
+        }
+
+        // the connection state changes while there are no new snapshots
+        loop {
+            tokio::select! {
+                changed = snapshot_rx.changed() => match changed {
+                    Ok(()) => break,
+                    Err(_) => return,
+                },
+                changed = connection_rx.changed() => {
+                    let connection = connection_rx.borrow_and_update().clone();
+                    if changed.is_err()
+                        || send_connection_state(&connection, &mut socket).await.is_err()
+                    {
+                        return;
+                    }
+                }
+            }
         }
     }
 }
 
+async fn send_connection_state(
+    connection: &ConnectionState,
+    socket: &mut WebSocket,
+) -> anyhow::Result<()> {
+    let template = t::ConnectionTemplate { state: connection }.render()?;
+    socket.send(template.into()).await?;
+    Ok(())
+}
+
 async fn send_mpd_status(
-    mpd: &mut Mpd,
+    mpd_status: &Status,
+    mpd: &Mpd,
     album_art_cache: Arc<Mutex<AlbumArtCache>>,
     socket: &mut WebSocket,
-    last_background: &mut Option<(Option<(String, String)>, t::Gradient)>,
+    last_background: &mut Option<(Option<CoverKey>, t::Gradient)>,
 ) -> anyhow::Result<()> {
-    let mpd_status = mpd.get_status().await?;
-
-    let album_art_key = mpd_status.artist.clone().zip(mpd_status.album.clone());
+    let album_art_key = mpd_status.cover_key();
 
     let background = match last_background {
         Some((ref last_key, bg)) if last_key == &album_art_key => {
@@ -89,17 +104,20 @@ async fn send_mpd_status(
 }
 
 async fn background_color(
-    album_art_key: Option<(String, String)>,
+    album_art_key: Option<CoverKey>,
     album_art_cache: Arc<Mutex<AlbumArtCache>>,
     mpd: &Mpd,
 ) -> t::Gradient {
     match album_art_key {
         Some(key) => {
-            let cover_bytes = get_set(key.clone(), album_art_cache, mpd).await;
+            let cover_bytes = get_set(key.clone(), Variant::default(), album_art_cache, mpd).await;
 
-            match cover_bytes {
-                Ok(cover_bytes) => {
-                    let cover_img = image::load_from_memory(&cover_bytes).unwrap();
+            // e.g. a cover cached by an older version that was cut short by a power cut
+            let cover_img = cover_bytes
+                .ok()
+                .and_then(|(cover_bytes, _)| image::load_from_memory(&cover_bytes).ok());
+            match cover_img {
+                Some(cover_img) => {
                     let settings = dominant_color_rs::Settings {
                         img_size: 64,
                         max_iters: 50,
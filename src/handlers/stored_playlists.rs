@@ -0,0 +1,244 @@
+use crate::error::{AppError, BadRequest};
+use crate::handlers::library::render_index;
+use crate::models::{
+    validate_playlist_name, ArtistAlbumQuery, PlaylistQuery, PlaylistSongQuery, UrlQuery,
+};
+use crate::mpd::Mpd;
+use crate::state::AppState;
+use crate::templates as t;
+use askama::Template;
+use axum::extract::ws::{WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use mpd_client::client::Subsystem;
+
+/// Text entered into the `hx-prompt` dialog. Browsers send header values as Latin-1, htmx
+/// percent-encodes anything else and says so in `HX-Prompt-URI-AutoEncoded`.
+fn prompt(headers: &HeaderMap) -> Result<String, BadRequest> {
+    let value = headers
+        .get("HX-Prompt")
+        .ok_or_else(|| BadRequest("Missing playlist name".to_string()))?;
+    let value = if headers.contains_key("HX-Prompt-URI-AutoEncoded") {
+        percent_encoding::percent_decode(value.as_bytes())
+            .decode_utf8()
+            .map_err(|_| BadRequest("Invalid playlist name".to_string()))?
+            .into_owned()
+    } else {
+        value.as_bytes().iter().map(|&b| b as char).collect()
+    };
+    let value = value.trim().to_string();
+    validate_playlist_name(&value)?;
+    Ok(value)
+}
+
+pub async fn get_stored_playlists(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let tabs = t::TabsTemplate {
+        stored_playlists_active: true,
+        ..state.tabs()
+    };
+
+    if headers.contains_key("HX-Request") {
+        Ok(t::StoredPlaylistsTemplate {
+            tabs: Some(tabs),
+            prefix: state.prefix,
+        }
+        .into_response())
+    } else {
+        let index = render_index(
+            &state,
+            t::Page::StoredPlaylists(t::StoredPlaylistsTemplate {
+                tabs: None,
+                prefix: state.prefix.clone(),
+            }),
+            tabs,
+        )
+        .await?;
+        Ok(index.into_response())
+    }
+}
+
+pub async fn get_stored_playlists_list(
+    State(state): State<AppState>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(|socket| handle_ws_stored_playlists(state, socket))
+}
+
+async fn send_stored_playlists(mpd: &Mpd, socket: &mut WebSocket) -> anyhow::Result<()> {
+    let playlists = mpd.get_stored_playlists().await?;
+    let template = t::StoredPlaylistsListTemplate { playlists }.render()?;
+    socket.send(template.into()).await?;
+
+    Ok(())
+}
+
+async fn handle_ws_stored_playlists(state: AppState, mut socket: WebSocket) {
+    let mpd = state.mpd;
+    let mut rx = state.event_tx.subscribe();
+
+    if send_stored_playlists(&mpd, &mut socket).await.is_err() {
+        return;
+    }
+    while let Ok(event) = rx.recv().await {
+        if event == Subsystem::StoredPlaylist
+            && send_stored_playlists(&mpd, &mut socket).await.is_err()
+        {
+            return;
+        }
+    }
+}
+
+pub async fn get_stored_playlist(
+    State(state): State<AppState>,
+    Query(q): Query<PlaylistQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let tabs = t::TabsTemplate {
+        stored_playlists_active: true,
+        ..state.tabs()
+    };
+
+    if headers.contains_key("HX-Request") {
+        Ok(t::StoredPlaylistTemplate {
+            tabs: Some(tabs),
+            prefix: state.prefix,
+            name: q.name,
+        }
+        .into_response())
+    } else {
+        let index = render_index(
+            &state,
+            t::Page::StoredPlaylist(t::StoredPlaylistTemplate {
+                tabs: None,
+                prefix: state.prefix.clone(),
+                name: q.name,
+            }),
+            tabs,
+        )
+        .await?;
+        Ok(index.into_response())
+    }
+}
+
+pub async fn get_stored_playlist_songs(
+    State(state): State<AppState>,
+    Query(q): Query<PlaylistQuery>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(|socket| handle_ws_stored_playlist_songs(state, q.name, socket))
+}
+
+async fn send_stored_playlist_songs(
+    mpd: &Mpd,
+    name: &str,
+    socket: &mut WebSocket,
+) -> anyhow::Result<()> {
+    let songs = mpd.get_stored_playlist(name).await?;
+    let template = t::StoredPlaylistSongsTemplate {
+        name: name.to_string(),
+        songs,
+    }
+    .render()?;
+    socket.send(template.into()).await?;
+
+    Ok(())
+}
+
+async fn handle_ws_stored_playlist_songs(state: AppState, name: String, mut socket: WebSocket) {
+    let mpd = state.mpd;
+    let mut rx = state.event_tx.subscribe();
+
+    if send_stored_playlist_songs(&mpd, &name, &mut socket)
+        .await
+        .is_err()
+    {
+        return;
+    }
+    while let Ok(event) = rx.recv().await {
+        if event == Subsystem::StoredPlaylist
+            && send_stored_playlist_songs(&mpd, &name, &mut socket)
+                .await
+                .is_err()
+        {
+            return;
+        }
+    }
+}
+
+pub async fn save_queue(State(state): State<AppState>, headers: HeaderMap) -> Result<(), AppError> {
+    let name = prompt(&headers)?;
+    state.mpd.save_queue_as_playlist(&name).await?;
+    Ok(())
+}
+
+pub async fn load_playlist(
+    State(state): State<AppState>,
+    Query(q): Query<PlaylistQuery>,
+) -> Result<(), AppError> {
+    state.mpd.load_playlist(&q.name).await?;
+    Ok(())
+}
+
+pub async fn play_playlist(
+    State(state): State<AppState>,
+    Query(q): Query<PlaylistQuery>,
+) -> Result<(), AppError> {
+    state.mpd.play_playlist(&q.name).await?;
+    Ok(())
+}
+
+pub async fn delete_playlist(
+    State(state): State<AppState>,
+    Query(q): Query<PlaylistQuery>,
+) -> Result<(), AppError> {
+    state.mpd.delete_playlist(&q.name).await?;
+    Ok(())
+}
+
+pub async fn rename_playlist(
+    State(state): State<AppState>,
+    Query(q): Query<PlaylistQuery>,
+    headers: HeaderMap,
+) -> Result<(), AppError> {
+    let to = prompt(&headers)?;
+    state.mpd.rename_playlist(&q.name, &to).await?;
+    Ok(())
+}
+
+pub async fn add_song(
+    State(state): State<AppState>,
+    Query(q): Query<UrlQuery>,
+    headers: HeaderMap,
+) -> Result<(), AppError> {
+    let name = prompt(&headers)?;
+    state.mpd.add_song_to_playlist(&name, &q.url).await?;
+    Ok(())
+}
+
+pub async fn add_album(
+    State(state): State<AppState>,
+    Query(q): Query<ArtistAlbumQuery>,
+    headers: HeaderMap,
+) -> Result<(), AppError> {
+    let name = prompt(&headers)?;
+    state
+        .mpd
+        .add_album_to_playlist(&name, q.artist_tag, &q.artist, &q.album)
+        .await?;
+    Ok(())
+}
+
+pub async fn remove_song(
+    State(state): State<AppState>,
+    Query(q): Query<PlaylistSongQuery>,
+) -> Result<(), AppError> {
+    state
+        .mpd
+        .remove_from_stored_playlist(&q.name, q.position)
+        .await?;
+    Ok(())
+}
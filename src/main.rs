@@ -1,18 +1,31 @@
+mod auth;
 mod cache;
+mod config;
+mod connection;
+mod csrf;
 mod error;
 mod handlers;
 mod models;
 mod mpd;
+mod party;
+mod radio;
 mod routes;
+mod snapshot;
 mod state;
 mod templates;
 
+use crate::auth::Auth;
 use crate::cache::AlbumArtCache;
+use crate::config::{Args, Config};
+use crate::csrf::CsrfToken;
 use crate::mpd::Mpd;
+use crate::party::Party;
+use crate::radio::Stations;
 use crate::routes::create_router;
+use crate::snapshot::SnapshotPublisher;
 use crate::state::AppState;
-use mpd_client::client::ConnectionEvent;
-use mpd_client::client::Subsystem;
+use clap::Parser;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::{broadcast, Mutex};
 use tower_http::trace::TraceLayer;
@@ -20,6 +33,20 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() {
+    let args = Args::parse();
+    let config = match Config::load(&args) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+    };
+
+    if args.print_config {
+        print!("{}", config.to_toml().expect("Failed to serialize config"));
+        return;
+    }
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
@@ -27,51 +54,79 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let album_art_cache: Arc<Mutex<AlbumArtCache>> = Mutex::new(AlbumArtCache::new()).into();
+    let stations = match Stations::load(&config.stations_file) {
+        Ok(stations) => Arc::new(Mutex::new(stations)),
+        Err(e) => {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+    };
 
-    let (mpd_client, mut connection_events) =
-        Mpd::connect().await.expect("Failed to connect to MPD");
-    let mpd = Mpd::new(mpd_client);
-    let (event_tx, _) = broadcast::channel(16);
+    let csrf_token = match CsrfToken::generate() {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("Failed to create CSRF token: {e:#}");
+            std::process::exit(1);
+        }
+    };
+    if config.legacy_get_actions {
+        tracing::warn!("Accepting GET for state-changing URLs, other sites can control MPD");
+    }
 
-    // MPD reconnection loop
-    let mpd_clone = mpd.clone();
-    let event_tx_clone = event_tx.clone();
-    tokio::spawn(async move {
-        loop {
-            while let Some(event) = connection_events.next().await {
-                if let ConnectionEvent::SubsystemChange(subsystem) = event {
-                    let _ = event_tx_clone.send(subsystem);
-                }
-            }
+    let auth = Auth::new(config.clone());
 
-            tracing::warn!("MPD connection lost, retrying in 5 seconds...");
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    let servers = config.servers();
+    let server_names: Arc<Vec<String>> = Arc::new(servers.iter().map(|s| s.name.clone()).collect());
 
-            match Mpd::connect().await {
-                Ok((new_client, new_events)) => {
-                    tracing::info!("Reconnected to MPD");
-                    mpd_clone.update_client(new_client).await;
-                    connection_events = new_events;
-                    // Trigger a refresh after reconnection
-                    let _ = event_tx_clone.send(Subsystem::Player);
-                }
-                Err(e) => {
-                    tracing::error!("Failed to reconnect to MPD: {}", e);
-                }
+    let mut states = vec![];
+    for server in servers {
+        let album_art_cache = match AlbumArtCache::open(
+            config.cache_dir.join(&server.name),
+            config.cache_max_bytes,
+            config.cache_size,
+        ) {
+            Ok(cache) => Arc::new(Mutex::new(cache)),
+            Err(e) => {
+                eprintln!("{e:#}");
+                std::process::exit(1);
             }
-        }
-    });
+        };
 
-    let state = AppState {
-        mpd,
-        album_art_cache,
-        event_tx,
-    };
+        let mpd = Mpd::new(config.clone());
+        let (event_tx, _) = broadcast::channel(16);
+        let server = Arc::new(server);
+        let (publisher, snapshot) = SnapshotPublisher::new();
+        let connection =
+            connection::spawn(server.clone(), mpd.clone(), event_tx.clone(), publisher);
+        let party = Arc::new(Party::new(config.clone()));
+        party::spawn_dj(party.clone(), mpd.clone(), event_tx.subscribe());
+
+        states.push(AppState {
+            mpd,
+            album_art_cache,
+            event_tx,
+            config: config.clone(),
+            prefix: format!("/s/{}", server.name),
+            server,
+            servers: server_names.clone(),
+            stations: stations.clone(),
+            snapshot,
+            connection,
+            csrf_token: csrf_token.clone(),
+            auth: auth.clone(),
+            party,
+        });
+    }
 
-    let app = create_router(state).layer(TraceLayer::new_for_http());
+    let app = create_router(states).layer(TraceLayer::new_for_http());
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
+    let listener = tokio::net::TcpListener::bind(&config.listen).await.unwrap();
     tracing::info!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    // client addresses are needed for the rate limits and votes of party mode
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
@@ -1,4 +1,12 @@
+use crate::cache::CoverKey;
+use crate::error::BadRequest;
+use crate::mpd::{tag_filter, ArtistTag, VolumeChange};
+use itertools::Itertools;
+use mpd_client::commands::{ReplayGainMode, SeekMode};
+use mpd_client::filter::{Filter, Operator};
+use mpd_client::tag::Tag;
 use serde::Deserialize;
+use std::time::Duration;
 
 #[derive(Deserialize)]
 pub struct GenericQuery {
@@ -6,8 +14,8 @@ pub struct GenericQuery {
 }
 
 #[derive(Deserialize)]
-pub struct ArtistQuery {
-    pub artist: String,
+pub struct LoginForm {
+    pub password: String,
 }
 
 #[derive(Deserialize)]
@@ -15,13 +23,443 @@ pub struct UrlQuery {
     pub url: String,
 }
 
+#[derive(Deserialize)]
+pub struct PathQuery {
+    #[serde(default)]
+    pub path: String,
+}
+
+#[derive(Deserialize)]
+pub struct StationQuery {
+    #[serde(default)]
+    pub name: String,
+    pub url: String,
+}
+
 #[derive(Deserialize)]
 pub struct ArtistAlbumQuery {
     pub artist: String,
     pub album: String,
+    #[serde(default)]
+    pub artist_tag: ArtistTag,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverSize {
+    /// Scaled down to `album_art_size`
+    #[default]
+    Thumbnail,
+    /// Scaled down to `album_art_medium_size`
+    Medium,
+    Original,
+}
+
+impl CoverSize {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoverSize::Thumbnail => "thumbnail",
+            CoverSize::Medium => "medium",
+            CoverSize::Original => "original",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverFormat {
+    /// The image as stored by MPD, or PNG when it was scaled down
+    #[default]
+    Auto,
+    Webp,
+}
+
+impl CoverFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoverFormat::Auto => "auto",
+            CoverFormat::Webp => "webp",
+        }
+    }
+}
+
+/// Either `uri` of a song or `artist` and `album`.
+#[derive(Deserialize)]
+pub struct CoverQuery {
+    pub uri: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    #[serde(default)]
+    pub artist_tag: ArtistTag,
+    #[serde(default)]
+    pub size: CoverSize,
+    #[serde(default)]
+    pub format: CoverFormat,
+}
+
+impl CoverQuery {
+    pub fn key(self) -> Result<CoverKey, BadRequest> {
+        match (self.uri, self.artist, self.album) {
+            (Some(uri), _, _) => Ok(CoverKey::Song(uri)),
+            (None, Some(artist), Some(album)) => {
+                Ok(CoverKey::Album((self.artist_tag, artist, album)))
+            }
+            _ => Err(BadRequest(
+                "Either uri or artist and album are required".to_string(),
+            )),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct SongIdQuery {
     pub song_id: Option<u64>,
 }
+
+#[derive(Deserialize)]
+pub struct SeekQuery {
+    pub pos: String,
+}
+
+impl SeekQuery {
+    /// Parses `pos` as absolute seconds (`42.5`) or relative to the current position (`+10`,
+    /// `-10`).
+    pub fn seek_mode(&self) -> Result<SeekMode, BadRequest> {
+        let pos = self.pos.trim();
+        let (seconds, mode): (&str, fn(Duration) -> SeekMode) =
+            if let Some(seconds) = pos.strip_prefix('+') {
+                (seconds, SeekMode::Forward)
+            } else if let Some(seconds) = pos.strip_prefix('-') {
+                (seconds, SeekMode::Backward)
+            } else {
+                (pos, SeekMode::Absolute)
+            };
+
+        seconds
+            .parse::<f64>()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .map(mode)
+            .ok_or_else(|| BadRequest(format!("Invalid seek position {:?}", self.pos)))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VolumeQuery {
+    pub value: String,
+}
+
+impl VolumeQuery {
+    /// Parses `value` as absolute volume (`0`-`100`) or a step (`+5`, `-5`, at most 100).
+    pub fn volume_change(&self) -> Result<VolumeChange, BadRequest> {
+        let value = self.value.trim();
+        let invalid = || BadRequest(format!("Invalid volume {:?}", self.value));
+
+        if value.starts_with('+') || value.starts_with('-') {
+            value
+                .parse::<i16>()
+                .ok()
+                .filter(|step| (-100..=100).contains(step))
+                .map(VolumeChange::Relative)
+                .ok_or_else(invalid)
+        } else {
+            value
+                .parse::<u8>()
+                .ok()
+                .filter(|volume| *volume <= 100)
+                .map(VolumeChange::Absolute)
+                .ok_or_else(invalid)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CrossfadeQuery {
+    pub seconds: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayGain {
+    Off,
+    Track,
+    Album,
+    Auto,
+}
+
+#[derive(Deserialize)]
+pub struct ReplayGainQuery {
+    pub mode: ReplayGain,
+}
+
+impl From<ReplayGain> for ReplayGainMode {
+    fn from(mode: ReplayGain) -> Self {
+        match mode {
+            ReplayGain::Off => ReplayGainMode::Off,
+            ReplayGain::Track => ReplayGainMode::Track,
+            ReplayGain::Album => ReplayGainMode::Album,
+            ReplayGain::Auto => ReplayGainMode::Auto,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MoveQuery {
+    pub to: usize,
+}
+
+#[derive(Deserialize)]
+pub struct SongMoveQuery {
+    pub song_id: u64,
+    pub to: usize,
+}
+
+#[derive(Deserialize)]
+pub struct RangeMoveQuery {
+    pub start: usize,
+    pub end: usize,
+    pub to: usize,
+}
+
+impl RangeMoveQuery {
+    pub fn validate(&self) -> Result<(), BadRequest> {
+        if self.start < self.end {
+            Ok(())
+        } else {
+            Err(BadRequest(format!(
+                "Invalid range {}..{}",
+                self.start, self.end
+            )))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistQuery {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistSongQuery {
+    pub name: String,
+    pub position: usize,
+}
+
+#[derive(Deserialize)]
+pub struct PartyRequestQuery {
+    pub id: u64,
+}
+
+#[derive(Deserialize)]
+pub struct PartyVoteQuery {
+    pub id: u64,
+    pub vote: Vote,
+}
+
+/// Vote on a party request; `none` takes a previous vote back.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Vote {
+    Up,
+    Down,
+    None,
+}
+
+impl Vote {
+    pub fn value(&self) -> i8 {
+        match self {
+            Vote::Up => 1,
+            Vote::Down => -1,
+            Vote::None => 0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RenameQuery {
+    pub to: String,
+}
+
+/// Stored playlist names are file names on the MPD side.
+pub fn validate_playlist_name(name: &str) -> Result<(), BadRequest> {
+    if name.trim().is_empty() || name.contains(['/', '\n', '\r']) {
+        Err(BadRequest(format!("Invalid playlist name {name:?}")))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchTag {
+    #[default]
+    Any,
+    Title,
+    Artist,
+    AlbumArtist,
+    Album,
+    Composer,
+    Genre,
+    File,
+}
+
+impl SearchTag {
+    pub const ALL: [SearchTag; 8] = [
+        SearchTag::Any,
+        SearchTag::Title,
+        SearchTag::Artist,
+        SearchTag::AlbumArtist,
+        SearchTag::Album,
+        SearchTag::Composer,
+        SearchTag::Genre,
+        SearchTag::File,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchTag::Any => "any",
+            SearchTag::Title => "title",
+            SearchTag::Artist => "artist",
+            SearchTag::AlbumArtist => "albumartist",
+            SearchTag::Album => "album",
+            SearchTag::Composer => "composer",
+            SearchTag::Genre => "genre",
+            SearchTag::File => "file",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SearchTag::Any => "Everything",
+            SearchTag::Title => "Title",
+            SearchTag::Artist => "Artist",
+            SearchTag::AlbumArtist => "Album artist",
+            SearchTag::Album => "Album",
+            SearchTag::Composer => "Composer",
+            SearchTag::Genre => "Genre",
+            SearchTag::File => "File name",
+        }
+    }
+
+    /// Tags to search in; `any` does not cover the file name, so it is searched separately.
+    pub fn tags(&self) -> Vec<Tag> {
+        match self {
+            SearchTag::Any => vec![Tag::any(), Tag::Other("file".into())],
+            SearchTag::Title => vec![Tag::Title],
+            SearchTag::Artist => vec![Tag::Artist],
+            SearchTag::AlbumArtist => vec![Tag::AlbumArtist],
+            SearchTag::Album => vec![Tag::Album],
+            SearchTag::Composer => vec![Tag::Composer],
+            SearchTag::Genre => vec![Tag::Genre],
+            SearchTag::File => vec![Tag::Other("file".into())],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: Option<String>,
+    #[serde(default)]
+    pub tag: SearchTag,
+}
+
+/// Library hierarchy: values of the tag, then their albums, then songs.
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Browse {
+    #[default]
+    Artist,
+    AlbumArtist,
+    Genre,
+    Composer,
+    Date,
+}
+
+impl Browse {
+    pub const ALL: [Browse; 5] = [
+        Browse::Artist,
+        Browse::AlbumArtist,
+        Browse::Genre,
+        Browse::Composer,
+        Browse::Date,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Browse::Artist => "artist",
+            Browse::AlbumArtist => "albumartist",
+            Browse::Genre => "genre",
+            Browse::Composer => "composer",
+            Browse::Date => "date",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Browse::Artist => "Artist",
+            Browse::AlbumArtist => "Album artist",
+            Browse::Genre => "Genre",
+            Browse::Composer => "Composer",
+            Browse::Date => "Year",
+        }
+    }
+
+    pub fn tag(&self) -> Tag {
+        match self {
+            Browse::Artist => Tag::Artist,
+            Browse::AlbumArtist => Tag::AlbumArtist,
+            Browse::Genre => Tag::Genre,
+            Browse::Composer => Tag::Composer,
+            Browse::Date => Tag::Date,
+        }
+    }
+
+    /// Only browsing by artist lists albums per track artist.
+    pub fn artist_tag(&self) -> ArtistTag {
+        match self {
+            Browse::Artist => ArtistTag::Artist,
+            _ => ArtistTag::AlbumArtist,
+        }
+    }
+
+    /// Turns the listed tag values into browsable entries; dates are reduced to their year.
+    pub fn entries(&self, values: Vec<String>) -> Vec<String> {
+        if *self != Browse::Date {
+            return values;
+        }
+        values
+            .into_iter()
+            .map(|date| match date.get(..4) {
+                Some(year) if year.chars().all(|c| c.is_ascii_digit()) => year.to_string(),
+                _ => date,
+            })
+            .unique()
+            .collect()
+    }
+
+    /// Matches songs under `value`. Years match any date within them (`1999-05-01`); mpd_client
+    /// has no prefix operator, and regular expressions need MPD to be built with PCRE.
+    pub fn filter(&self, value: &str) -> Filter {
+        if *self == Browse::Date && value.len() == 4 && value.chars().all(|c| c.is_ascii_digit()) {
+            tag_filter(Tag::Date, Operator::Contain, value)
+        } else {
+            tag_filter(self.tag(), Operator::Equal, value)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BrowseQuery {
+    #[serde(default)]
+    pub by: Browse,
+    pub q: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AlbumsQuery {
+    #[serde(default)]
+    pub by: Browse,
+    /// `artist` is accepted for links from before other hierarchies existed
+    #[serde(alias = "artist")]
+    pub value: String,
+}
@@ -1,51 +1,347 @@
-use anyhow::Result;
+use crate::cache::CoverKey;
+use crate::config::{Config, MpdAddress, ServerConfig};
+use crate::error::NotFound;
+use crate::models::{CoverFormat, CoverSize};
+use anyhow::{anyhow, Context, Result};
 use bytes::{Bytes, BytesMut};
+use mpd_client::client::{CommandError, ConnectWithPasswordError};
+use mpd_client::commands::{ReplayGainMode, SingleMode};
 use mpd_client::responses::PlayState;
-use std::io::Read;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashSet;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+#[derive(Serialize)]
 pub struct Album {
     pub album_name: String,
     pub year: Option<i32>,
+    pub artist: String,
+    pub artist_tag: ArtistTag,
+}
+
+/// Tag an album's `artist` refers to. Browsing by artist keeps the albums of each track artist
+/// apart, the other hierarchies keep compilations together under their album artist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtistTag {
+    #[default]
+    Artist,
+    AlbumArtist,
 }
 
+impl ArtistTag {
+    pub fn tag(&self) -> mpd_client::tag::Tag {
+        match self {
+            ArtistTag::Artist => mpd_client::tag::Tag::Artist,
+            ArtistTag::AlbumArtist => mpd_client::tag::Tag::AlbumArtist,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtistTag::Artist => "artist",
+            ArtistTag::AlbumArtist => "albumartist",
+        }
+    }
+}
+
+#[derive(Serialize)]
 pub struct Status {
+    /// URI of the current song
+    pub url: Option<String>,
     pub title: Option<String>,
     pub artist: Option<String>,
     pub album: Option<String>,
+    pub album_artist: Option<String>,
+    /// Station name of a stream
+    pub name: Option<String>,
+    /// The current song is a stream (e.g. internet radio) rather than a file
+    pub stream: bool,
     // pub year: Option<i32>,
+    #[serde(serialize_with = "serialize_play_state")]
     pub play_state: mpd_client::responses::PlayState,
     pub has_next: bool,
     pub has_prev: bool,
     pub has_song: bool,
+    /// Position of the current song in the queue
+    pub song_position: Option<usize>,
+    #[serde(serialize_with = "serialize_single_mode")]
     pub single_mode: mpd_client::commands::SingleMode,
     pub repeat: bool,
     pub random: bool,
-    // pub consume: bool,
+    pub consume: bool,
+    /// Crossfade in seconds
+    pub crossfade: u64,
+    #[serde(serialize_with = "serialize_replay_gain_mode")]
+    pub replay_gain_mode: mpd_client::commands::ReplayGainMode,
+    #[serde(rename = "updating_db")]
     pub ubdating_db: bool,
     pub elapsed: Option<f64>,
     pub duration: Option<f64>,
+    pub volume: u8,
+}
+
+impl Status {
+    /// Cover of the current song. Songs are keyed by their album like in the library, so that
+    /// the cover is fetched and cached once per album; songs without album by their URI.
+    pub fn cover_key(&self) -> Option<CoverKey> {
+        let url = self.url.as_ref().filter(|_| !self.stream)?;
+        let album = match (&self.album_artist, &self.artist) {
+            (Some(album_artist), _) => (ArtistTag::AlbumArtist, album_artist),
+            (None, Some(artist)) => (ArtistTag::Artist, artist),
+            (None, None) => return Some(CoverKey::Song(url.clone())),
+        };
+        Some(match &self.album {
+            Some(name) => CoverKey::Album((album.0, album.1.clone(), name.clone())),
+            None => CoverKey::Song(url.clone()),
+        })
+    }
 }
 
+#[derive(Clone, Serialize)]
 pub struct SongInQueue {
     pub id: u64,
+    pub position: usize,
     pub title: String,
     pub artist: String,
     pub playing: bool,
 }
 
+#[derive(Serialize)]
 pub struct Song {
     pub url: String,
-    // pub artist: String,
-    // pub album: String,
     pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub track: Option<u64>,
+    pub disc: Option<u64>,
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    pub genre: Option<String>,
+    /// Audio format as `samplerate:bits:channels`
+    pub format: Option<String>,
     pub year: Option<i32>,
 }
 
+impl From<mpd_client::responses::Song> for Song {
+    fn from(song: mpd_client::responses::Song) -> Self {
+        let (disc, track) = song.number();
+        Song {
+            title: song.title().map(|s| s.to_string()).unwrap_or_default(),
+            artist: song.artists().first().cloned().unwrap_or_default(),
+            album: song.album().map(|s| s.to_string()).unwrap_or_default(),
+            track: Some(track).filter(|track| *track > 0),
+            disc: Some(disc).filter(|disc| *disc > 0),
+            duration: song.duration.map(|duration| duration.as_secs_f64()),
+            genre: get_single_tag_value(&song, &mpd_client::tag::Tag::Genre),
+            year: get_year(&song),
+            format: song.format,
+            url: song.url,
+        }
+    }
+}
+
+/// Where a cover was found, see [`Mpd::album_art`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoverSource {
+    Override,
+    AlbumArt,
+    ReadPicture,
+    Fallback,
+}
+
+impl CoverSource {
+    pub const ALL: [CoverSource; 4] = [
+        CoverSource::Override,
+        CoverSource::AlbumArt,
+        CoverSource::ReadPicture,
+        CoverSource::Fallback,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoverSource::Override => "override",
+            CoverSource::AlbumArt => "albumart",
+            CoverSource::ReadPicture => "readpicture",
+            CoverSource::Fallback => "fallback",
+        }
+    }
+}
+
+#[derive(Default, Serialize)]
+pub struct Directory {
+    pub path: String,
+    pub directories: Vec<DirectoryEntry>,
+    pub files: Vec<FileEntry>,
+}
+
+#[derive(Serialize)]
+pub struct DirectoryEntry {
+    pub path: String,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct FileEntry {
+    pub url: String,
+    pub name: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct StoredPlaylist {
+    pub name: String,
+    pub last_modified: String,
+}
+
+#[derive(Serialize)]
+pub struct StoredPlaylistSong {
+    pub position: usize,
+    pub url: String,
+    pub title: String,
+    pub artist: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct SearchResults {
+    pub artists: Vec<String>,
+    pub albums: Vec<SearchAlbum>,
+    pub songs: Vec<SearchSong>,
+    /// More songs matched than `MAX_SEARCH_SONGS`
+    pub truncated: bool,
+}
+
+#[derive(Serialize, PartialEq)]
+pub struct SearchAlbum {
+    pub artist: String,
+    pub album: String,
+}
+
+#[derive(Serialize)]
+pub struct SearchSong {
+    pub url: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+}
+
+/// Caps the number of songs returned by `Mpd::search`, short queries match most of the library.
+pub const MAX_SEARCH_SONGS: usize = 200;
+
+/// `search` command: like `find`, but case-insensitive. Not provided by `mpd_client`.
+struct Search(mpd_client::filter::Filter);
+
+impl mpd_client::commands::Command for Search {
+    type Response = Vec<mpd_client::responses::Song>;
+
+    fn command(&self) -> mpd_client::protocol::command::Command {
+        mpd_client::protocol::command::Command::new("search").argument(self.0.clone())
+    }
+
+    fn response(
+        self,
+        frame: mpd_client::protocol::response::Frame,
+    ) -> Result<Self::Response, mpd_client::responses::TypedResponseError> {
+        // the response has the same shape as for `find`
+        mpd_client::commands::Find::new(self.0).response(frame)
+    }
+}
+
+/// `add` command: adds a song or, recursively, a whole directory. `mpd_client` only has
+/// `addid`, which does not accept directories.
+struct AddUri<'a>(&'a str);
+
+impl mpd_client::commands::Command for AddUri<'_> {
+    type Response = ();
+
+    fn command(&self) -> mpd_client::protocol::command::Command {
+        mpd_client::protocol::command::Command::new("add").argument(self.0)
+    }
+
+    fn response(
+        self,
+        _frame: mpd_client::protocol::response::Frame,
+    ) -> Result<Self::Response, mpd_client::responses::TypedResponseError> {
+        Ok(())
+    }
+}
+
+/// `lsinfo` command: subdirectories and songs directly in a directory.
+struct LsInfo<'a>(&'a str);
+
+impl mpd_client::commands::Command for LsInfo<'_> {
+    type Response = (Vec<String>, Vec<mpd_client::responses::Song>);
+
+    fn command(&self) -> mpd_client::protocol::command::Command {
+        let command = mpd_client::protocol::command::Command::new("lsinfo");
+        if self.0.is_empty() {
+            command
+        } else {
+            command.argument(self.0)
+        }
+    }
+
+    fn response(
+        self,
+        frame: mpd_client::protocol::response::Frame,
+    ) -> Result<Self::Response, mpd_client::responses::TypedResponseError> {
+        let directories = frame
+            .fields()
+            .filter(|(key, _)| *key == "directory")
+            .map(|(_, value)| value.to_string())
+            .collect();
+        // `listallinfo` songs are parsed the same way, skipping directory entries
+        let songs = mpd_client::commands::ListAllIn::root().response(frame)?;
+        Ok((directories, songs))
+    }
+}
+
+fn serialize_play_state<S: Serializer>(state: &PlayState, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(match state {
+        PlayState::Stopped => "stopped",
+        PlayState::Playing => "playing",
+        PlayState::Paused => "paused",
+    })
+}
+
+fn serialize_single_mode<S: Serializer>(mode: &SingleMode, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(match mode {
+        SingleMode::Enabled => "on",
+        SingleMode::Disabled => "off",
+        SingleMode::Oneshot => "oneshot",
+    })
+}
+
+pub enum VolumeChange {
+    Absolute(u8),
+    Relative(i16),
+}
+
+/// Volume restored by unmuting when the level before muting is not known.
+const DEFAULT_UNMUTE_VOLUME: u8 = 50;
+
+fn serialize_replay_gain_mode<S: Serializer>(
+    mode: &ReplayGainMode,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    s.serialize_str(match mode {
+        ReplayGainMode::Off => "off",
+        ReplayGainMode::Track => "track",
+        ReplayGainMode::Album => "album",
+        ReplayGainMode::Auto => "auto",
+    })
+}
+
 #[derive(Clone)]
 pub struct Mpd {
-    client: Arc<RwLock<mpd_client::Client>>,
+    /// `None` until the first connection succeeds
+    client: Arc<RwLock<Option<mpd_client::Client>>>,
+    /// Volume before the last mute, restored by the next unmute
+    muted_volume: Arc<RwLock<Option<u8>>>,
+    config: Arc<Config>,
 }
 
 pub fn get_single_tag_value<T>(
@@ -61,51 +357,121 @@ where
         .and_then(|d| d.parse::<T>().ok())
 }
 
-pub fn mpd_addr() -> String {
-    let host = std::env::var("MPD_HOST").unwrap_or("localhost".to_string());
-    let port = std::env::var("MPD_PORT").unwrap_or("6600".to_string());
-    format!("{host}:{port}")
+/// Filter on `tag` with the value escaped for MPD. The filter expression is quoted again on the
+/// command line, but `mpd_client` escapes the value only once (and wrongly: `"` becomes `\\"`),
+/// so quotes and backslashes are escaped here for the second level.
+pub fn tag_filter(
+    tag: mpd_client::tag::Tag,
+    operator: mpd_client::filter::Operator,
+    value: &str,
+) -> mpd_client::filter::Filter {
+    mpd_client::filter::Filter::new(
+        tag,
+        operator,
+        value.replace('\\', "\\\\\\\\").replace('"', "\\\""),
+    )
 }
 
-pub fn scale_down_if_needed(bytes: BytesMut, max_size: u32) -> Result<Bytes> {
-    let img = image::load_from_memory(&bytes)?;
-    if img.width() > max_size {
-        let resized = image::imageops::resize(
-            &img,
-            max_size,
-            max_size,
-            image::imageops::FilterType::Triangle,
-        );
-        let mut cursor = std::io::Cursor::new(vec![]);
-        resized.write_to(&mut cursor, image::ImageFormat::Png)?;
-        Ok(Bytes::from(cursor.into_inner()))
-    } else {
-        Ok(bytes.freeze())
+/// Year of the `Date` tag, which may also be a full date (`1999-05-01`).
+pub fn get_year(song: &mpd_client::responses::Song) -> Option<i32> {
+    song.tags
+        .get(&mpd_client::tag::Tag::Date)
+        .and_then(|tag_values| tag_values.first())
+        .and_then(|d| d.get(..4))
+        .and_then(|year| year.parse().ok())
+}
+
+/// Scales the cover down to fit into `max_size` and encodes it as requested. Scaled down covers
+/// are PNG unless WebP is asked for.
+pub fn convert_cover(bytes: Bytes, max_size: Option<u32>, format: CoverFormat) -> Result<Bytes> {
+    if max_size.is_none() && format == CoverFormat::Auto {
+        return Ok(bytes);
+    }
+
+    let mut img = image::load_from_memory(&bytes)?;
+    let too_large = max_size.filter(|max_size| img.width() > *max_size || img.height() > *max_size);
+    match (too_large, format) {
+        (None, CoverFormat::Auto) => return Ok(bytes),
+        (Some(max_size), _) => {
+            img = img.resize(max_size, max_size, image::imageops::FilterType::Triangle);
+        }
+        (None, CoverFormat::Webp) => {}
+    }
+
+    let mut cursor = std::io::Cursor::new(vec![]);
+    match format {
+        CoverFormat::Auto => img.write_to(&mut cursor, image::ImageFormat::Png)?,
+        // the WebP encoder only takes 8 bit RGB(A)
+        CoverFormat::Webp => image::DynamicImage::ImageRgba8(img.into_rgba8())
+            .write_to(&mut cursor, image::ImageFormat::WebP)?,
     }
+    Ok(Bytes::from(cursor.into_inner()))
 }
 
 impl Mpd {
-    pub fn new(client: mpd_client::Client) -> Self {
+    /// Creates a handle that is not connected yet, see `update_client`.
+    pub fn new(config: Arc<Config>) -> Self {
         Self {
-            client: Arc::new(RwLock::new(client)),
+            client: Arc::new(RwLock::new(None)),
+            muted_volume: Arc::new(RwLock::new(None)),
+            config,
         }
     }
 
     pub async fn update_client(&self, client: mpd_client::Client) {
         let mut w = self.client.write().await;
-        *w = client;
+        *w = Some(client);
     }
 
-    pub async fn connect() -> Result<(mpd_client::Client, mpd_client::client::ConnectionEvents)> {
-        let addr = mpd_addr();
-        let connection = tokio::net::TcpStream::connect(addr).await?;
-        let (mpd_client, connection_events) = mpd_client::Client::connect(connection).await?;
-        Ok((mpd_client, connection_events))
+    /// The current connection; commands fail like on a lost connection before the first one.
+    async fn client(&self) -> Result<mpd_client::Client, CommandError> {
+        self.client
+            .read()
+            .await
+            .clone()
+            .ok_or(CommandError::ConnectionClosed)
     }
 
-    pub async fn get_artists(&self, name_filter: &Option<String>) -> Result<Vec<String>> {
-        let cmd = mpd_client::commands::List::new(mpd_client::tag::Tag::Artist);
-        let response = self.client.read().await.command(cmd).await?;
+    pub async fn connect(
+        server: &ServerConfig,
+    ) -> Result<(mpd_client::Client, mpd_client::client::ConnectionEvents)> {
+        let password = server.mpd_password();
+        let result = match server.mpd_address() {
+            MpdAddress::Tcp(addr) => {
+                let connection = tokio::net::TcpStream::connect(addr).await?;
+                mpd_client::Client::connect_with_password_opt(connection, password).await
+            }
+            MpdAddress::Unix(path) => {
+                let connection = tokio::net::UnixStream::connect(path).await?;
+                mpd_client::Client::connect_with_password_opt(connection, password).await
+            }
+        };
+
+        match result {
+            Ok((mpd_client, connection_events)) => Ok((mpd_client, connection_events)),
+            Err(ConnectWithPasswordError::IncorrectPassword) => Err(anyhow!(
+                "MPD rejected the password, check the password@host form of MPD_HOST"
+            )),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Edge length covers of `size` are scaled down to.
+    pub fn cover_size(&self, size: CoverSize) -> Option<u32> {
+        match size {
+            CoverSize::Thumbnail => Some(self.config.album_art_size),
+            CoverSize::Medium => Some(self.config.album_art_medium_size),
+            CoverSize::Original => None,
+        }
+    }
+
+    pub async fn get_tag_values(
+        &self,
+        tag: mpd_client::tag::Tag,
+        name_filter: &Option<String>,
+    ) -> Result<Vec<String>> {
+        let cmd = mpd_client::commands::List::new(tag);
+        let response = self.client().await?.command(cmd).await?;
 
         let name_filter = name_filter.as_ref().map(|v| v.to_lowercase());
         Ok(response
@@ -120,104 +486,315 @@ impl Mpd {
             .collect::<Vec<_>>())
     }
 
-    pub async fn get_songs(&self, artist: &str, album: &str) -> Result<Vec<Song>> {
+    /// Searches songs whose `tags` contain `query` (case-insensitive) and groups them by artist
+    /// and album.
+    pub async fn search(
+        &self,
+        query: &str,
+        tags: Vec<mpd_client::tag::Tag>,
+    ) -> Result<SearchResults> {
+        if query.trim().is_empty() {
+            return Ok(SearchResults::default());
+        }
+        let commands = tags
+            .into_iter()
+            .map(|tag| {
+                Search(tag_filter(
+                    tag,
+                    mpd_client::filter::Operator::Contain,
+                    query.trim(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        let mut songs = self
+            .client()
+            .await?
+            .command_list(commands)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        // songs matched by several tags are returned once per tag
+        let mut seen = HashSet::new();
+        songs.retain(|song| seen.insert(song.url.clone()));
+
+        let artist_of = |song: &mpd_client::responses::Song| {
+            song.artists()
+                .first()
+                .map(|s| s.to_string())
+                .unwrap_or_default()
+        };
+        songs.sort_by_cached_key(|song| {
+            (
+                artist_of(song).to_lowercase(),
+                song.album().map(|s| s.to_lowercase()),
+                song.number(),
+            )
+        });
+
+        let mut results = SearchResults::default();
+        for song in songs.iter() {
+            let artist = artist_of(song);
+            if !artist.is_empty() && !results.artists.contains(&artist) {
+                results.artists.push(artist.clone());
+            }
+            if let Some(album) = song.album() {
+                let album = SearchAlbum {
+                    artist,
+                    album: album.to_string(),
+                };
+                if !results.albums.contains(&album) {
+                    results.albums.push(album);
+                }
+            }
+        }
+        results.truncated = songs.len() > MAX_SEARCH_SONGS;
+        results.songs = songs
+            .into_iter()
+            .take(MAX_SEARCH_SONGS)
+            .map(|song| SearchSong {
+                artist: artist_of(&song),
+                title: song.title().map(|s| s.to_string()).unwrap_or_default(),
+                album: song.album().map(|s| s.to_string()).unwrap_or_default(),
+                url: song.url,
+            })
+            .collect();
+        Ok(results)
+    }
+
+    /// Songs of the album, in disc and track order. Unknown albums are an error.
+    pub async fn get_songs(
+        &self,
+        artist_tag: ArtistTag,
+        artist: &str,
+        album: &str,
+    ) -> Result<Vec<Song>> {
+        let songs = self.find_album_songs(artist_tag, artist, album).await?;
+        if songs.is_empty() {
+            return Err(NotFound(format!("No album {album:?} by {artist:?}")).into());
+        }
+        Ok(songs)
+    }
+
+    async fn find_album_songs(
+        &self,
+        artist_tag: ArtistTag,
+        artist: &str,
+        album: &str,
+    ) -> Result<Vec<Song>> {
         let cmd = mpd_client::commands::Find::new(
-            mpd_client::filter::Filter::new(
-                mpd_client::tag::Tag::Artist,
+            tag_filter(
+                artist_tag.tag(),
                 mpd_client::filter::Operator::Equal,
-                artist.replace("\"", "\\\""),
+                artist,
             )
-            .and(mpd_client::filter::Filter::new(
+            .and(tag_filter(
                 mpd_client::tag::Tag::Album,
                 mpd_client::filter::Operator::Equal,
-                album.replace("\"", "\\\""),
+                album,
             )),
         );
-        let mut result = self.client.read().await.command(cmd).await?;
+        let mut result = self.client().await?.command(cmd).await?;
 
-        result.sort_by_key(|song| song.number());
-        Ok(result
-            .into_iter()
-            .map(|song| Song {
-                // artist: get_single_tag_value(&song, &mpd_client::tag::Tag::Artist).unwrap_or_default(),
-                // album: get_single_tag_value(&song, &mpd_client::tag::Tag::Album).unwrap_or_default(),
-                title: get_single_tag_value(&song, &mpd_client::tag::Tag::Title)
-                    .unwrap_or_default(),
-                year: get_single_tag_value(&song, &mpd_client::tag::Tag::Date),
-                url: song.url,
-            })
-            .collect())
+        result.sort_by(|a, b| a.number().cmp(&b.number()).then_with(|| a.url.cmp(&b.url)));
+        Ok(result.into_iter().map(Song::from).collect())
     }
 
-    pub async fn album_art(&self, artist: &str, album: &str) -> Result<Bytes> {
-        let fallback = || {
-            let mut file = std::fs::File::open("assets/lp.png").unwrap();
-            let mut buf = vec![];
-            file.read_to_end(&mut buf).unwrap();
-            Bytes::from(buf)
-        };
+    /// Song of the music database with the URL. Unknown songs are an error.
+    pub async fn get_song(&self, url: &str) -> Result<Song> {
+        let mut songs = self.find_song(url).await?;
+        if songs.is_empty() {
+            return Err(NotFound(format!("No song {url:?}")).into());
+        }
+        Ok(Song::from(songs.swap_remove(0)))
+    }
 
-        let url = self
-            .get_songs(artist, album)
+    async fn find_song(&self, url: &str) -> Result<Vec<mpd_client::responses::Song>> {
+        let filter = tag_filter(
+            mpd_client::tag::Tag::Other("file".into()),
+            mpd_client::filter::Operator::Equal,
+            url,
+        );
+        Ok(self
+            .client()
             .await?
-            .first()
-            .map(|first_song| first_song.url.clone());
-        if url.is_none() {
-            return Ok(fallback());
+            .command(mpd_client::commands::Find::new(filter))
+            .await?)
+    }
+
+    /// Cover of the album and where it was found. Looks for each song for a cover file in its
+    /// directory (`albumart`) and a picture embedded in the song (`readpicture`), and falls back
+    /// to `fallback_image`. Override images are looked for first by `cache::get_set`.
+    pub async fn album_art(
+        &self,
+        artist_tag: ArtistTag,
+        artist: &str,
+        album: &str,
+    ) -> Result<(Bytes, CoverSource)> {
+        let songs = self.find_album_songs(artist_tag, artist, album).await?;
+        let mut directories = vec![];
+        for song in &songs {
+            // `albumart` only depends on the directory of the song
+            let directory = song.url.rsplit_once('/').map_or("", |(dir, _)| dir);
+            if !directories.contains(&directory) {
+                directories.push(directory);
+                if let Some(art) = self.read_picture(&song.url, false).await? {
+                    return Ok((art, CoverSource::AlbumArt));
+                }
+            }
+            if let Some(art) = self.read_picture(&song.url, true).await? {
+                return Ok((art, CoverSource::ReadPicture));
+            }
         }
 
-        let art = self
-            .client
-            .read()
-            .await
-            .album_art(&url.unwrap())
-            .await?
-            .and_then(|(bytes, _)| scale_down_if_needed(bytes, 256).ok());
+        Ok((self.fallback_image().await?, CoverSource::Fallback))
+    }
 
-        if let Some(art) = art {
-            Ok(art)
-        } else {
-            Ok(fallback())
+    /// Cover of a single song: the cover file of its directory, then the picture embedded in it,
+    /// in the same order as `album_art`.
+    pub async fn song_art(&self, url: &str) -> Result<(Bytes, CoverSource)> {
+        if let Some(art) = self.read_picture(url, false).await? {
+            return Ok((art, CoverSource::AlbumArt));
         }
+        if let Some(art) = self.read_picture(url, true).await? {
+            return Ok((art, CoverSource::ReadPicture));
+        }
+        Ok((self.fallback_image().await?, CoverSource::Fallback))
     }
 
-    pub async fn get_albums(&self, artist: &str) -> Result<Vec<Album>> {
-        let response = self
-            .client
-            .read()
+    pub async fn fallback_image(&self) -> Result<Bytes> {
+        let path = &self.config.fallback_image;
+        let buf = tokio::fs::read(path)
             .await
-            .command(
-                mpd_client::commands::List::new(mpd_client::tag::Tag::Album).filter(
-                    mpd_client::filter::Filter::new(
-                        mpd_client::tag::Tag::Artist,
-                        mpd_client::filter::Operator::Equal,
-                        artist.replace("\"", "\\\""),
-                    ),
-                ),
-            )
-            .await?;
+            .with_context(|| format!("Failed to read fallback image {}", path.display()))?;
+        Ok(Bytes::from(buf))
+    }
 
-        let album_names = response.into_iter().collect::<Vec<String>>();
-        let mut albums = vec![];
-        for album_name in album_names {
-            let songs = self.get_songs(artist, &album_name).await?;
-            let first_song = songs.first();
-            let year = first_song.and_then(|song| song.year);
+    /// Image in `album_art_dir` taking precedence over MPD for the cover. Songs are looked up to
+    /// find their album.
+    pub async fn override_art_path(&self, key: &CoverKey) -> Result<Option<PathBuf>> {
+        if self.config.album_art_dir.is_none() {
+            return Ok(None);
+        }
+        match key {
+            CoverKey::Album((_, artist, album)) => Ok(self.override_path(artist, album).await),
+            CoverKey::Song(url) => {
+                let songs = self.find_song(url).await?;
+                let Some(song) = songs.first() else {
+                    return Ok(None);
+                };
+                match (song.artists().first(), song.album()) {
+                    (Some(artist), Some(album)) => Ok(self.override_path(artist, album).await),
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
 
-            albums.push(Album { album_name, year });
+    /// `<album_art_dir>/<artist>/<album>.<jpg|jpeg|png|webp>`, if it exists.
+    async fn override_path(&self, artist: &str, album: &str) -> Option<PathBuf> {
+        let dir = self.config.album_art_dir.as_ref()?;
+        let file_name = |name: &str| match name {
+            "" | "." | ".." => None,
+            name => Some(name.replace(['/', '\\'], "_")),
+        };
+        let dir = dir.join(file_name(artist)?);
+        let album = file_name(album)?;
+        for ext in ["jpg", "jpeg", "png", "webp"] {
+            let path = dir.join(format!("{album}.{ext}"));
+            if tokio::fs::metadata(&path)
+                .await
+                .is_ok_and(|metadata| metadata.is_file())
+            {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    /// Reads the cover file of the song's directory (`albumart`) or the picture embedded in the
+    /// song (`readpicture`). MPD errors, e.g. for missing files or unsupported formats, mean
+    /// there is no picture.
+    async fn read_picture(&self, url: &str, embedded: bool) -> Result<Option<Bytes>> {
+        let client = self.client().await?;
+        let mut data = BytesMut::new();
+        loop {
+            let response = if embedded {
+                let cmd = mpd_client::commands::AlbumArtEmbedded::new(url).offset(data.len());
+                client.command(cmd).await
+            } else {
+                let cmd = mpd_client::commands::AlbumArt::new(url).offset(data.len());
+                client.command(cmd).await
+            };
+            let response = match response {
+                Ok(Some(response)) => response,
+                Ok(None) => return Ok(None),
+                Err(CommandError::ErrorResponse { error, .. }) => {
+                    tracing::debug!(target: "album_art", "no picture for {url}: {}", error.message);
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            };
+            data.extend_from_slice(&response.data);
+            if response.data.is_empty() || data.len() >= response.size {
+                return Ok(Some(data.freeze()));
+            }
+        }
+    }
+
+    /// Albums with songs matching `filter`. When `artist` is given, all albums are attributed to
+    /// it, otherwise to the first `artist_tag` of their songs.
+    pub async fn get_albums(
+        &self,
+        filter: mpd_client::filter::Filter,
+        artist_tag: ArtistTag,
+        artist: Option<&str>,
+    ) -> Result<Vec<Album>> {
+        let songs = self
+            .client()
+            .await?
+            .command(mpd_client::commands::Find::new(filter))
+            .await?;
+
+        let mut albums: Vec<Album> = vec![];
+        let mut seen = HashSet::new();
+        for song in songs {
+            let Some(album_name) = song.album() else {
+                continue;
+            };
+            let album_artist = match (artist, artist_tag) {
+                (Some(artist), _) => Some(artist),
+                // MPD falls back to the artist for songs without album artist
+                (None, ArtistTag::AlbumArtist) => song
+                    .album_artists()
+                    .first()
+                    .or_else(|| song.artists().first())
+                    .map(|s| s.as_str()),
+                (None, ArtistTag::Artist) => song.artists().first().map(|s| s.as_str()),
+            }
+            .unwrap_or_default();
+            if !seen.insert((album_artist.to_string(), album_name.to_string())) {
+                continue;
+            }
+            albums.push(Album {
+                album_name: album_name.to_string(),
+                year: get_year(&song),
+                artist: album_artist.to_string(),
+                artist_tag,
+            });
         }
         albums.sort_by_key(|album| album.year);
         Ok(albums)
     }
 
     pub async fn get_status(&self) -> Result<Status> {
-        let (status, current_song) = self
-            .client
-            .read()
-            .await
+        let (status, current_song, replay_gain) = self
+            .client()
+            .await?
             .command_list((
                 mpd_client::commands::Status,
                 mpd_client::commands::CurrentSong,
+                mpd_client::commands::ReplayGainStatus,
             ))
             .await?;
 
@@ -238,6 +815,17 @@ impl Mpd {
         let album = current_song
             .as_ref()
             .and_then(|song| song.song.album().map(|s| s.to_string()));
+        let album_artist = current_song
+            .as_ref()
+            .and_then(|song| song.song.album_artists().first().map(|s| s.to_string()));
+        let name = current_song.as_ref().and_then(|song| {
+            get_single_tag_value::<String>(&song.song, &mpd_client::tag::Tag::Name)
+        });
+        let url = current_song.as_ref().map(|song| song.song.url.clone());
+        // MPD doesn't know how long streams are
+        let stream = current_song
+            .as_ref()
+            .is_some_and(|song| song.song.duration.is_none());
         // let year = current_song
         //     .as_ref()
         //     .and_then(|song| get_single_tag_value::<i32>(&song.song, &mpd_client::tag::Tag::Date));
@@ -245,64 +833,75 @@ impl Mpd {
         let duration = status.duration.map(|d| d.as_secs_f64());
 
         Ok(Status {
+            url,
             title,
             artist,
             album,
+            album_artist,
+            name,
+            stream,
             // year,
             play_state,
             has_next,
             has_prev,
             has_song,
-            // consume: status.consume,
+            song_position: status.current_song.map(|(position, _)| position.0),
             single_mode: status.single,
             repeat: status.repeat,
             random: status.random,
+            consume: status.consume,
+            crossfade: status.crossfade.as_secs(),
+            replay_gain_mode: replay_gain.mode,
             ubdating_db: status.update_job.is_some(),
             elapsed,
             duration,
+            volume: status.volume,
         })
     }
 
     pub async fn prev(&self) -> Result<()> {
-        self.client
-            .read()
-            .await
+        self.client()
+            .await?
             .command(mpd_client::commands::Previous)
             .await?;
         Ok(())
     }
 
     pub async fn next(&self) -> Result<()> {
-        self.client
-            .read()
-            .await
+        self.client()
+            .await?
             .command(mpd_client::commands::Next)
             .await?;
         Ok(())
     }
 
     pub async fn pause(&self, pause: bool) -> Result<()> {
-        self.client
-            .read()
-            .await
+        self.client()
+            .await?
             .command(mpd_client::commands::SetPause(pause))
             .await?;
         Ok(())
     }
 
+    pub async fn seek(&self, mode: mpd_client::commands::SeekMode) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::Seek(mode))
+            .await?;
+        Ok(())
+    }
+
     pub async fn play(&self) -> Result<()> {
-        self.client
-            .read()
-            .await
+        self.client()
+            .await?
             .command(mpd_client::commands::Play::current())
             .await?;
         Ok(())
     }
 
     pub async fn play_song(&self, song_id: u64) -> Result<()> {
-        self.client
-            .read()
-            .await
+        self.client()
+            .await?
             .command(mpd_client::commands::Play::song(
                 mpd_client::commands::SongId(song_id),
             ))
@@ -310,35 +909,68 @@ impl Mpd {
         Ok(())
     }
 
+    /// Replaces the queue with the song, or all songs beneath the directory, and plays it.
     pub async fn play_song_by_url(&self, url: &str) -> Result<()> {
-        let song_id = self
-            .client
-            .read()
-            .await
-            .command_list((
-                mpd_client::commands::ClearQueue,
-                mpd_client::commands::Add::uri(url),
-            ))
+        self.client()
             .await?
-            .1;
-        self.play_song(song_id.0).await?;
-        Ok(())
+            .command_list((mpd_client::commands::ClearQueue, AddUri(url)))
+            .await?;
+        self.play().await
     }
 
+    /// Appends the song, or all songs beneath the directory, to the queue.
     pub async fn append_song_by_url(&self, url: &str) -> Result<()> {
-        self.client
-            .read()
-            .await
+        self.client().await?.command(AddUri(url)).await?;
+        Ok(())
+    }
+
+    /// Appends a single song to the queue, returns its id.
+    pub async fn append_song(&self, url: &str) -> Result<u64> {
+        let id = self
+            .client()
+            .await?
             .command(mpd_client::commands::Add::uri(url))
             .await?;
-        Ok(())
+        Ok(id.0)
+    }
+
+    /// Contents of a directory of the music database, `""` is the root.
+    pub async fn list_directory(&self, path: &str) -> Result<Directory> {
+        let path = path.trim_matches('/');
+        let (directories, songs) = self.client().await?.command(LsInfo(path)).await?;
+
+        let name_of = |path: &str| path.rsplit('/').next().unwrap_or(path).to_string();
+        let mut directories = directories
+            .into_iter()
+            .map(|path| DirectoryEntry {
+                name: name_of(&path),
+                path,
+            })
+            .collect::<Vec<_>>();
+        directories.sort_by_key(|directory| directory.name.to_lowercase());
+
+        let mut files = songs
+            .into_iter()
+            .map(|song| FileEntry {
+                name: name_of(&song.url),
+                title: song.title().map(|s| s.to_string()),
+                artist: song.artists().first().map(|s| s.to_string()),
+                url: song.url,
+            })
+            .collect::<Vec<_>>();
+        files.sort_by_key(|file| file.name.to_lowercase());
+
+        Ok(Directory {
+            path: path.to_string(),
+            directories,
+            files,
+        })
     }
 
     pub async fn get_playlist(&self) -> Result<Vec<SongInQueue>> {
         let (queue, current_song) = self
-            .client
-            .read()
-            .await
+            .client()
+            .await?
             .command_list((
                 mpd_client::commands::Queue,
                 mpd_client::commands::CurrentSong,
@@ -349,6 +981,7 @@ impl Mpd {
             .iter()
             .map(|song| SongInQueue {
                 id: song.id.0,
+                position: song.position.0,
                 artist: song
                     .song
                     .artists()
@@ -364,58 +997,214 @@ impl Mpd {
     }
 
     pub async fn remove_from_playlist(&self, song_id: u64) -> Result<()> {
-        self.client
-            .read()
-            .await
+        self.client()
+            .await?
             .command(mpd_client::commands::Delete::id(song_id.into()))
             .await?;
         Ok(())
     }
 
+    pub async fn move_song(&self, song_id: u64, to: usize) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::Move::id(song_id.into()).to_position(to.into()))
+            .await?;
+        Ok(())
+    }
+
+    /// Moves the song right after the currently playing one.
+    pub async fn play_next(&self, song_id: u64) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::Move::id(song_id.into()).after_current(0))
+            .await?;
+        Ok(())
+    }
+
+    /// Moves songs at positions `start..end` so that the first of them ends up at `to`.
+    pub async fn move_range(&self, start: usize, end: usize, to: usize) -> Result<()> {
+        self.client()
+            .await?
+            .command(
+                mpd_client::commands::Move::range(
+                    mpd_client::commands::SongPosition(start)
+                        ..mpd_client::commands::SongPosition(end),
+                )
+                .to_position(to.into()),
+            )
+            .await?;
+        Ok(())
+    }
+
     pub async fn clear_playlist(&self) -> Result<()> {
-        self.client
-            .read()
-            .await
+        self.client()
+            .await?
             .command(mpd_client::commands::ClearQueue)
             .await?;
         Ok(())
     }
 
-    pub async fn append_album_to_playlist(&self, artist: &str, album: &str) -> Result<()> {
-        let songs = self.get_songs(artist, album).await?;
+    pub async fn append_album_to_playlist(
+        &self,
+        artist_tag: ArtistTag,
+        artist: &str,
+        album: &str,
+    ) -> Result<()> {
+        let songs = self.get_songs(artist_tag, artist, album).await?;
         let commands = songs
             .iter()
             .map(|song| mpd_client::commands::Add::uri(&song.url))
             .collect::<Vec<_>>();
-        self.client.read().await.command_list(commands).await?;
+        self.client().await?.command_list(commands).await?;
         Ok(())
     }
 
-    pub async fn play_album(&self, artist: &str, album: &str) -> Result<()> {
+    pub async fn play_album(&self, artist_tag: ArtistTag, artist: &str, album: &str) -> Result<()> {
+        let songs = self.get_songs(artist_tag, artist, album).await?;
         self.clear_playlist().await?;
-        let songs = self.get_songs(artist, album).await?;
         let commands = songs
             .iter()
             .map(|song| mpd_client::commands::Add::uri(&song.url))
             .collect::<Vec<_>>();
-        let ids = self.client.read().await.command_list(commands).await?;
+        let ids = self.client().await?.command_list(commands).await?;
         if let Some(id) = ids.first() {
             self.play_song(id.0).await?;
         }
         Ok(())
     }
 
+    pub async fn get_stored_playlists(&self) -> Result<Vec<StoredPlaylist>> {
+        let mut playlists = self
+            .client()
+            .await?
+            .command(mpd_client::commands::GetPlaylists)
+            .await?;
+        playlists.sort_by_key(|playlist| playlist.name.to_lowercase());
+        Ok(playlists
+            .into_iter()
+            .map(|playlist| StoredPlaylist {
+                last_modified: playlist.last_modified.raw().to_string(),
+                name: playlist.name,
+            })
+            .collect())
+    }
+
+    pub async fn get_stored_playlist(&self, name: &str) -> Result<Vec<StoredPlaylistSong>> {
+        let songs = self
+            .client()
+            .await?
+            .command(mpd_client::commands::GetPlaylist(name))
+            .await?;
+        Ok(songs
+            .into_iter()
+            .enumerate()
+            .map(|(position, song)| StoredPlaylistSong {
+                position,
+                artist: song
+                    .artists()
+                    .first()
+                    .map(|s| s.to_string())
+                    .unwrap_or_default(),
+                title: song.title().map(|s| s.to_string()).unwrap_or_default(),
+                url: song.url,
+            })
+            .collect())
+    }
+
+    pub async fn save_queue_as_playlist(&self, name: &str) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::SaveQueueAsPlaylist(name))
+            .await?;
+        Ok(())
+    }
+
+    /// Appends the stored playlist to the queue.
+    pub async fn load_playlist(&self, name: &str) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::LoadPlaylist::name(name))
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces the queue with the stored playlist and starts playing it. The queue is kept
+    /// when the playlist can't be read.
+    pub async fn play_playlist(&self, name: &str) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .command(mpd_client::commands::GetPlaylist(name))
+            .await?;
+        client
+            .command_list((
+                mpd_client::commands::ClearQueue,
+                mpd_client::commands::LoadPlaylist::name(name),
+                mpd_client::commands::Play::current(),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_playlist(&self, name: &str) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::DeletePlaylist(name))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn rename_playlist(&self, from: &str, to: &str) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::RenamePlaylist::new(from, to))
+            .await?;
+        Ok(())
+    }
+
+    /// Adds the song to the stored playlist, creating the playlist if it does not exist.
+    pub async fn add_song_to_playlist(&self, name: &str, url: &str) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::AddToPlaylist::new(name, url))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn add_album_to_playlist(
+        &self,
+        name: &str,
+        artist_tag: ArtistTag,
+        artist: &str,
+        album: &str,
+    ) -> Result<()> {
+        let songs = self.get_songs(artist_tag, artist, album).await?;
+        let commands = songs
+            .iter()
+            .map(|song| mpd_client::commands::AddToPlaylist::new(name, &song.url))
+            .collect::<Vec<_>>();
+        self.client().await?.command_list(commands).await?;
+        Ok(())
+    }
+
+    pub async fn remove_from_stored_playlist(&self, name: &str, position: usize) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::RemoveFromPlaylist::position(
+                name, position,
+            ))
+            .await?;
+        Ok(())
+    }
+
     pub async fn toggle_repeat(&self) -> Result<()> {
         let repeat = self
-            .client
-            .read()
-            .await
+            .client()
+            .await?
             .command(mpd_client::commands::Status)
             .await?
             .repeat;
-        self.client
-            .read()
-            .await
+        self.client()
+            .await?
             .command(mpd_client::commands::SetRepeat(!repeat))
             .await?;
         Ok(())
@@ -423,35 +1212,172 @@ impl Mpd {
 
     pub async fn toggle_random(&self) -> Result<()> {
         let random = self
-            .client
-            .read()
-            .await
+            .client()
+            .await?
             .command(mpd_client::commands::Status)
             .await?
             .random;
-        self.client
-            .read()
-            .await
+        self.client()
+            .await?
             .command(mpd_client::commands::SetRandom(!random))
             .await?;
         Ok(())
     }
 
+    pub async fn set_volume(&self, change: VolumeChange) -> Result<()> {
+        let volume = match change {
+            VolumeChange::Absolute(volume) => volume.min(100),
+            VolumeChange::Relative(delta) => {
+                let current = self
+                    .client()
+                    .await?
+                    .command(mpd_client::commands::Status)
+                    .await?
+                    .volume;
+                i16::from(current).saturating_add(delta).clamp(0, 100) as u8
+            }
+        };
+        self.client()
+            .await?
+            .command(mpd_client::commands::SetVolume(volume))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn toggle_mute(&self) -> Result<()> {
+        let volume = self
+            .client()
+            .await?
+            .command(mpd_client::commands::Status)
+            .await?
+            .volume;
+        let mut muted_volume = self.muted_volume.write().await;
+        let new_volume = if volume > 0 {
+            *muted_volume = Some(volume);
+            0
+        } else {
+            muted_volume.take().unwrap_or(DEFAULT_UNMUTE_VOLUME)
+        };
+        self.client()
+            .await?
+            .command(mpd_client::commands::SetVolume(new_volume))
+            .await?;
+        Ok(())
+    }
+
+    /// Cycles single mode off -> on -> oneshot -> off.
+    pub async fn cycle_single(&self) -> Result<()> {
+        let single = self
+            .client()
+            .await?
+            .command(mpd_client::commands::Status)
+            .await?
+            .single;
+        let next = match single {
+            SingleMode::Disabled => SingleMode::Enabled,
+            SingleMode::Enabled => SingleMode::Oneshot,
+            SingleMode::Oneshot => SingleMode::Disabled,
+        };
+        self.client()
+            .await?
+            .command(mpd_client::commands::SetSingle(next))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn toggle_consume(&self) -> Result<()> {
+        let consume = self
+            .client()
+            .await?
+            .command(mpd_client::commands::Status)
+            .await?
+            .consume;
+        self.client()
+            .await?
+            .command(mpd_client::commands::SetConsume(!consume))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_crossfade(&self, seconds: u64) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::Crossfade(
+                std::time::Duration::from_secs(seconds),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_replay_gain_mode(&self, mode: ReplayGainMode) -> Result<()> {
+        self.client()
+            .await?
+            .command(mpd_client::commands::SetReplayGainMode(mode))
+            .await?;
+        Ok(())
+    }
+
     pub async fn stats(&self) -> Result<mpd_client::responses::Stats> {
         Ok(self
-            .client
-            .read()
-            .await
+            .client()
+            .await?
             .command(mpd_client::commands::Stats)
             .await?)
     }
 
     pub async fn update_db(&self) -> Result<()> {
-        self.client
-            .read()
-            .await
+        self.client()
+            .await?
             .command(mpd_client::commands::Update::new())
             .await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_client::filter::Operator;
+    use mpd_client::protocol::command::Argument;
+    use mpd_client::tag::Tag;
+
+    /// Reads the quoted string at the start of `s` like MPD does, returns it and the rest.
+    fn unquote(s: &str) -> (String, &str) {
+        let mut value = String::new();
+        let mut chars = s.char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => value.push(chars.next().unwrap().1),
+                '"' => return (value, &s[i + 1..]),
+                c => value.push(c),
+            }
+        }
+        panic!("Closing quote not found in {s:?}");
+    }
+
+    /// Value of the rendered filter, as MPD sees it after unquoting the command argument and
+    /// then the value within the filter expression.
+    fn filter_value(value: &str) -> String {
+        let mut buf = BytesMut::new();
+        tag_filter(Tag::Artist, Operator::Equal, value).render(&mut buf);
+        let (expression, rest) = unquote(std::str::from_utf8(&buf).unwrap());
+        assert_eq!(rest, "");
+        let (value, rest) = unquote(expression.strip_prefix("(Artist == ").unwrap());
+        assert_eq!(rest, ")");
+        value
+    }
+
+    #[test]
+    fn tag_filter_escapes_quotes() {
+        assert_eq!(
+            filter_value(r#""Weird Al" Yankovic"#),
+            r#""Weird Al" Yankovic"#
+        );
+    }
+
+    #[test]
+    fn tag_filter_escapes_backslashes() {
+        assert_eq!(filter_value(r"AC\DC"), r"AC\DC");
+        assert_eq!(filter_value(r#"\"#), r#"\"#);
+    }
+}
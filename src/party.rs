@@ -0,0 +1,290 @@
+use crate::auth::Role;
+use crate::config::Config;
+use crate::error::{BadRequest, NotFound, TooManyRequests};
+use crate::models::Vote;
+use crate::mpd::{Mpd, Song};
+use anyhow::Result;
+use mpd_client::client::{CommandError, Subsystem};
+use mpd_client::responses::PlayState;
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, Mutex, Notify};
+
+/// `party_requests_per_hour` counts the requests of this long.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Songs requested in party mode that are not queued yet. One per server, kept in memory only.
+pub struct Party {
+    config: Arc<Config>,
+    requests: Mutex<Requests>,
+    /// Wakes the DJ when the next request may have changed
+    changed: Notify,
+}
+
+#[derive(Default)]
+struct Requests {
+    next_id: u64,
+    pending: Vec<SongRequest>,
+    /// When guests requested songs per IP address, within the last `RATE_LIMIT_WINDOW`
+    recent: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+struct SongRequest {
+    id: u64,
+    url: String,
+    title: String,
+    artist: String,
+    approved: bool,
+    requested_by: String,
+    /// +1 or -1 per client, the requester's own upvote included
+    votes: HashMap<String, i8>,
+}
+
+impl SongRequest {
+    fn score(&self) -> i64 {
+        self.votes.values().map(|&vote| i64::from(vote)).sum()
+    }
+
+    /// Only admins see the requests of others that wait for approval.
+    fn visible_to(&self, client: &str, role: Role) -> bool {
+        self.approved || self.requested_by == client || role == Role::Admin
+    }
+}
+
+/// A pending request as seen by one client.
+pub struct RequestView {
+    pub id: u64,
+    pub title: String,
+    pub artist: String,
+    pub score: i64,
+    pub approved: bool,
+    /// The client's own vote
+    pub vote: i8,
+    /// Requested by the client
+    pub mine: bool,
+}
+
+impl Party {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            config,
+            requests: Mutex::new(Requests::default()),
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn needs_approval(&self) -> bool {
+        self.config.party_approval
+    }
+
+    /// Adds the song to the requests, or upvotes it when it was requested already. Guests are
+    /// rate limited by IP address, and their requests wait for approval with `party_approval`.
+    /// `client` identifies the browser, see `handlers::party::Client`.
+    pub async fn request(&self, client: &str, ip: IpAddr, role: Role, song: Song) -> Result<()> {
+        let mut requests = self.requests.lock().await;
+        if let Some(request) = requests
+            .pending
+            .iter_mut()
+            .find(|request| request.url == song.url)
+        {
+            if !request.visible_to(client, role) {
+                return Err(BadRequest(
+                    "The song was requested already and waits for approval".into(),
+                )
+                .into());
+            }
+            request.votes.insert(client.to_string(), 1);
+            drop(requests);
+            self.changed.notify_one();
+            return Ok(());
+        }
+
+        if role == Role::Guest {
+            let now = Instant::now();
+            requests.recent.retain(|_, times| {
+                while times
+                    .front()
+                    .is_some_and(|time| now.duration_since(*time) >= RATE_LIMIT_WINDOW)
+                {
+                    times.pop_front();
+                }
+                !times.is_empty()
+            });
+            let limit = self.config.party_requests_per_hour;
+            let times = requests.recent.entry(ip).or_default();
+            if times.len() >= limit {
+                let wait = times
+                    .front()
+                    .map_or(RATE_LIMIT_WINDOW, |time| {
+                        RATE_LIMIT_WINDOW.saturating_sub(now.duration_since(*time))
+                    })
+                    .as_secs()
+                    .div_ceil(60);
+                return Err(TooManyRequests(format!(
+                    "Only {limit} requests per hour, try again in {wait} min"
+                ))
+                .into());
+            }
+            times.push_back(now);
+        }
+
+        requests.next_id += 1;
+        let id = requests.next_id;
+        let title = if song.title.is_empty() {
+            song.url.clone()
+        } else {
+            song.title
+        };
+        requests.pending.push(SongRequest {
+            id,
+            url: song.url,
+            title,
+            artist: song.artist,
+            approved: role > Role::Guest || !self.config.party_approval,
+            requested_by: client.to_string(),
+            votes: HashMap::from([(client.to_string(), 1)]),
+        });
+        drop(requests);
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    /// Votes on a request the client can see in `list`.
+    pub async fn vote(&self, client: &str, role: Role, id: u64, vote: Vote) -> Result<()> {
+        let mut requests = self.requests.lock().await;
+        let request = find(&mut requests.pending, id)?;
+        if !request.visible_to(client, role) {
+            return Err(not_found());
+        }
+        match vote {
+            Vote::None => request.votes.remove(client),
+            _ => request.votes.insert(client.to_string(), vote.value()),
+        };
+        drop(requests);
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    pub async fn approve(&self, id: u64) -> Result<()> {
+        find(&mut self.requests.lock().await.pending, id)?.approved = true;
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    pub async fn remove(&self, id: u64) -> Result<()> {
+        let mut requests = self.requests.lock().await;
+        find(&mut requests.pending, id)?;
+        requests.pending.retain(|request| request.id != id);
+        Ok(())
+    }
+
+    /// Requests in the order they will be queued, as far as the client may see them.
+    pub async fn list(&self, client: &str, role: Role) -> Vec<RequestView> {
+        let requests = self.requests.lock().await;
+        ranked(&requests.pending)
+            .filter(|request| request.visible_to(client, role))
+            .map(|request| RequestView {
+                id: request.id,
+                title: request.title.clone(),
+                artist: request.artist.clone(),
+                score: request.score(),
+                approved: request.approved,
+                vote: request.votes.get(client).copied().unwrap_or_default(),
+                mine: request.requested_by == client,
+            })
+            .collect()
+    }
+
+    /// Appends the top approved request to the queue when MPD is about to run out of songs:
+    /// the current song is the last one, or the queue has been played to the end. Music that
+    /// was stopped on purpose stays stopped.
+    async fn queue_next(&self, mpd: &Mpd) -> Result<()> {
+        let next = ranked(&self.requests.lock().await.pending)
+            .find(|request| request.approved)
+            .map(|request| (request.id, request.url.clone()));
+        let Some((id, url)) = next else {
+            return Ok(());
+        };
+
+        let status = mpd.get_status().await?;
+        let idle = match status.play_state {
+            PlayState::Stopped => !status.has_song,
+            _ => !status.has_next,
+        };
+        if !idle {
+            return Ok(());
+        }
+
+        match mpd.append_song(&url).await {
+            Ok(song_id) => {
+                tracing::info!("Queued party request {url}");
+                self.requests
+                    .lock()
+                    .await
+                    .pending
+                    .retain(|request| request.id != id);
+                if status.play_state == PlayState::Stopped {
+                    mpd.play_song(song_id).await?;
+                }
+                Ok(())
+            }
+            Err(e) => {
+                // e.g. the song was removed from the database since; don't try again
+                if let Some(CommandError::ErrorResponse { .. }) = e.downcast_ref() {
+                    tracing::warn!("Dropping party request {url}: {e}");
+                    self.requests
+                        .lock()
+                        .await
+                        .pending
+                        .retain(|request| request.id != id);
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+fn find(pending: &mut [SongRequest], id: u64) -> Result<&mut SongRequest> {
+    pending
+        .iter_mut()
+        .find(|request| request.id == id)
+        .ok_or_else(not_found)
+}
+
+fn not_found() -> anyhow::Error {
+    NotFound("The request was queued or removed already".to_string()).into()
+}
+
+/// Highest score first, earlier requests first among equals.
+fn ranked(pending: &[SongRequest]) -> impl Iterator<Item = &SongRequest> {
+    let mut ranked: Vec<_> = pending.iter().collect();
+    ranked.sort_by_key(|request| (Reverse(request.score()), request.id));
+    ranked.into_iter()
+}
+
+/// Feeds the requests of one server into its queue, checking whenever the current song or the
+/// queue changes (see `Party::queue_next`) and whenever the requests do.
+pub fn spawn_dj(party: Arc<Party>, mpd: Mpd, mut events: broadcast::Receiver<Subsystem>) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = party.queue_next(&mpd).await {
+                tracing::debug!("Failed to queue party request: {e}");
+            }
+
+            loop {
+                tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(Subsystem::Player | Subsystem::Queue) | Err(RecvError::Lagged(_)) => break,
+                        Ok(_) => {}
+                        Err(RecvError::Closed) => return,
+                    },
+                    _ = party.changed.notified() => break,
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,90 @@
+use crate::error::BadRequest;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Station {
+    pub name: String,
+    pub url: String,
+}
+
+/// Favourite radio stations, kept in a TOML file shared by all MPD servers.
+pub struct Stations {
+    path: PathBuf,
+    stations: Vec<Station>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StationsFile {
+    #[serde(default)]
+    stations: Vec<Station>,
+}
+
+impl Stations {
+    /// Loads the stations from `path`; a missing file means no stations yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = if path.exists() {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read stations file {}", path.display()))?;
+            toml::from_str(&content)
+                .with_context(|| format!("Failed to parse stations file {}", path.display()))?
+        } else {
+            StationsFile::default()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            stations: file.stations,
+        })
+    }
+
+    pub fn list(&self) -> Vec<Station> {
+        self.stations.clone()
+    }
+
+    /// Adds the station, or renames it when the URL is already listed.
+    pub async fn add(&mut self, name: &str, url: &str) -> Result<()> {
+        let url = url.trim();
+        if !is_stream_url(url) {
+            return Err(BadRequest(format!("Invalid stream URL {url:?}")).into());
+        }
+        let name = match name.trim() {
+            "" => url,
+            name => name,
+        };
+
+        let mut stations = self.stations.clone();
+        match stations.iter_mut().find(|station| station.url == url) {
+            Some(station) => station.name = name.to_string(),
+            None => stations.push(Station {
+                name: name.to_string(),
+                url: url.to_string(),
+            }),
+        }
+        self.save(stations).await
+    }
+
+    pub async fn remove(&mut self, url: &str) -> Result<()> {
+        let mut stations = self.stations.clone();
+        stations.retain(|station| station.url != url);
+        self.save(stations).await
+    }
+
+    /// Writes `stations` to the file and only then takes them over, so that the list doesn't
+    /// change when it can't be saved.
+    async fn save(&mut self, stations: Vec<Station>) -> Result<()> {
+        let file = StationsFile { stations };
+        let content = toml::to_string(&file)?;
+        tokio::fs::write(&self.path, content)
+            .await
+            .with_context(|| format!("Failed to write stations file {}", self.path.display()))?;
+        self.stations = file.stations;
+        Ok(())
+    }
+}
+
+/// Streams are played by URL, songs of the music database by their path.
+pub fn is_stream_url(url: &str) -> bool {
+    url.split_once("://")
+        .is_some_and(|(scheme, rest)| !scheme.is_empty() && !rest.is_empty())
+}
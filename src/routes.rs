@@ -1,39 +1,524 @@
-use crate::handlers::{album_art, controls, database, library, now_playing, playlist, status};
+use crate::auth::{authenticate, require_role, Role};
+use crate::csrf::check_origin;
+use crate::error::negotiate_error;
+use crate::handlers::{
+    album_art, api, controls, database, files, library, login, now_playing, party, playlist, radio,
+    search, status, stored_playlists,
+};
 use crate::state::AppState;
-use axum::{routing::get, Router};
+use axum::{
+    handler::Handler,
+    middleware,
+    routing::{delete, get, post, MethodRouter},
+    Extension, Router,
+};
 use tower_http::services::ServeDir;
 
-pub fn create_router(state: AppState) -> Router {
+/// Serves every server under `/s/{name}` and the first one also at the root.
+pub fn create_router(states: Vec<AppState>) -> Router {
+    let assets_dir = states[0].config.assets_dir.clone();
+    let csrf_token = states[0].csrf_token.clone();
+    let auth = states[0].auth.clone();
+    let actions = Actions {
+        legacy_get: states[0].config.legacy_get_actions,
+    };
+    let root_state = AppState {
+        prefix: String::new(),
+        ..states[0].clone()
+    };
+
+    let mut router = server_routes(&actions).with_state(root_state);
+    for state in states {
+        router = router.nest(
+            &format!("/s/{}", state.server.name),
+            server_routes(&actions).with_state(state),
+        );
+    }
+    let login_routes = Router::new()
+        .route("/login", get(login::get_login).post(login::login))
+        .route("/logout", post(login::logout))
+        .layer(Extension(csrf_token.clone()))
+        .with_state(auth.clone());
+
+    router
+        .merge(login_routes)
+        .nest_service("/assets", ServeDir::new(assets_dir))
+        .layer(middleware::from_fn_with_state(auth, authenticate))
+        .layer(middleware::from_fn_with_state(csrf_token, check_origin))
+        .layer(middleware::from_fn(negotiate_error))
+}
+
+/// Routes of state-changing handlers, available to the given role and above. Those of the web UI
+/// are also reachable by GET with `legacy_get_actions` (without protection against cross-site
+/// requests).
+struct Actions {
+    legacy_get: bool,
+}
+
+impl Actions {
+    fn post<H, T>(&self, role: Role, handler: H) -> MethodRouter<AppState>
+    where
+        H: Handler<T, AppState>,
+        T: 'static,
+    {
+        self.with_legacy_get(role, post(handler.clone()), handler)
+    }
+
+    fn delete<H, T>(&self, role: Role, handler: H) -> MethodRouter<AppState>
+    where
+        H: Handler<T, AppState>,
+        T: 'static,
+    {
+        self.with_legacy_get(role, delete(handler.clone()), handler)
+    }
+
+    /// Also restricts the route to `role` and above.
+    fn with_legacy_get<H, T>(
+        &self,
+        role: Role,
+        route: MethodRouter<AppState>,
+        handler: H,
+    ) -> MethodRouter<AppState>
+    where
+        H: Handler<T, AppState>,
+        T: 'static,
+    {
+        let route = if self.legacy_get {
+            route.get(handler)
+        } else {
+            route
+        };
+        route.route_layer(middleware::from_fn_with_state(role, require_role))
+    }
+}
+
+fn server_routes(actions: &Actions) -> Router<AppState> {
     Router::new()
         .route("/", get(library::get_index))
         .route("/library", get(library::get_library))
         .route("/albums", get(library::get_albums))
         .route("/songs", get(library::get_songs))
+        .route("/search", get(search::get_search))
+        .route("/files", get(files::get_files))
+        .route("/radio", get(radio::get_radio))
+        .route("/radio/add", actions.post(Role::User, radio::add_station))
+        .route(
+            "/radio/remove",
+            actions.delete(Role::User, radio::remove_station),
+        )
+        .route("/party", get(party::get_party))
+        .route("/party/search", get(party::search_songs))
+        .route("/party/requests", get(party::get_requests))
+        .route(
+            "/party/request",
+            actions.post(Role::Guest, party::request_song),
+        )
+        .route("/party/vote", actions.post(Role::Guest, party::vote))
+        .route("/party/approve", actions.post(Role::Admin, party::approve))
+        .route("/party/remove", actions.delete(Role::Admin, party::remove))
         .route("/status", get(status::get_status))
-        .route("/control/play", get(controls::control_play_song))
-        .route("/control/unpause", get(controls::control_unpause))
-        .route("/control/pause", get(controls::control_pause))
-        .route("/control/prev", get(controls::control_prev))
-        .route("/control/next", get(controls::control_next))
-        .route("/control/toggle_repeat", get(controls::toggle_repeat))
-        .route("/control/toggle_random", get(controls::toggle_random))
+        .route(
+            "/control/play",
+            actions.post(Role::User, controls::control_play_song),
+        )
+        .route(
+            "/control/unpause",
+            actions.post(Role::User, controls::control_unpause),
+        )
+        .route(
+            "/control/pause",
+            actions.post(Role::User, controls::control_pause),
+        )
+        .route(
+            "/control/prev",
+            actions.post(Role::User, controls::control_prev),
+        )
+        .route(
+            "/control/next",
+            actions.post(Role::User, controls::control_next),
+        )
+        .route(
+            "/control/seek",
+            actions.post(Role::User, controls::control_seek),
+        )
+        .route(
+            "/control/volume",
+            actions.post(Role::User, controls::control_volume),
+        )
+        .route(
+            "/control/toggle_mute",
+            actions.post(Role::User, controls::toggle_mute),
+        )
+        .route(
+            "/control/toggle_repeat",
+            actions.post(Role::User, controls::toggle_repeat),
+        )
+        .route(
+            "/control/toggle_random",
+            actions.post(Role::User, controls::toggle_random),
+        )
+        .route(
+            "/control/cycle_single",
+            actions.post(Role::User, controls::cycle_single),
+        )
+        .route(
+            "/control/toggle_consume",
+            actions.post(Role::User, controls::toggle_consume),
+        )
+        .route(
+            "/control/crossfade",
+            actions.post(Role::User, controls::control_crossfade),
+        )
+        .route(
+            "/control/replay_gain",
+            actions.post(Role::User, controls::control_replay_gain),
+        )
         .route("/playlist", get(playlist::get_playlist))
-        .route("/playlist/clear", get(playlist::clear_playlist))
+        .route(
+            "/playlist/clear",
+            actions.delete(Role::User, playlist::clear_playlist),
+        )
         .route("/playlist/songs", get(playlist::get_playlist_songs))
-        .route("/playlist/append/album", get(playlist::append_album))
-        .route("/playlist/play/album", get(playlist::play_album))
-        .route("/playlist/play/song", get(playlist::play_song_by_url))
-        .route("/playlist/append/song", get(playlist::append_song_by_url))
-        .route("/playlist/remove/song", get(playlist::remove_song_by_id))
+        .route(
+            "/playlist/append/album",
+            actions.post(Role::Guest, playlist::append_album),
+        )
+        .route(
+            "/playlist/play/album",
+            actions.post(Role::User, playlist::play_album),
+        )
+        .route(
+            "/playlist/play/song",
+            actions.post(Role::User, playlist::play_song_by_url),
+        )
+        .route(
+            "/playlist/append/song",
+            actions.post(Role::Guest, playlist::append_song_by_url),
+        )
+        .route(
+            "/playlist/remove/song",
+            actions.delete(Role::User, playlist::remove_song_by_id),
+        )
+        .route(
+            "/playlist/move/song",
+            actions.post(Role::User, playlist::move_song),
+        )
+        .route(
+            "/playlist/move/range",
+            actions.post(Role::User, playlist::move_range),
+        )
+        .route(
+            "/playlist/play_next",
+            actions.post(Role::User, playlist::play_next),
+        )
+        .route("/playlists", get(stored_playlists::get_stored_playlists))
+        .route(
+            "/playlists/list",
+            get(stored_playlists::get_stored_playlists_list),
+        )
+        .route(
+            "/playlists/playlist",
+            get(stored_playlists::get_stored_playlist),
+        )
+        .route(
+            "/playlists/playlist/songs",
+            get(stored_playlists::get_stored_playlist_songs),
+        )
+        .route(
+            "/playlists/save",
+            actions.post(Role::User, stored_playlists::save_queue),
+        )
+        .route(
+            "/playlists/load",
+            actions.post(Role::User, stored_playlists::load_playlist),
+        )
+        .route(
+            "/playlists/play",
+            actions.post(Role::User, stored_playlists::play_playlist),
+        )
+        .route(
+            "/playlists/delete",
+            actions.delete(Role::User, stored_playlists::delete_playlist),
+        )
+        .route(
+            "/playlists/rename",
+            actions.post(Role::User, stored_playlists::rename_playlist),
+        )
+        .route(
+            "/playlists/add/song",
+            actions.post(Role::User, stored_playlists::add_song),
+        )
+        .route(
+            "/playlists/add/album",
+            actions.post(Role::User, stored_playlists::add_album),
+        )
+        .route(
+            "/playlists/remove/song",
+            actions.delete(Role::User, stored_playlists::remove_song),
+        )
         .route("/cover", get(album_art::get_cover))
         .route("/database", get(database::get_database))
-        .route("/database/update_db", get(database::update_db))
+        .route(
+            "/database/update_db",
+            actions.post(Role::Admin, database::update_db),
+        )
         .route("/database/update_status", get(database::update_status))
+        .route(
+            "/database/purge_cache",
+            actions.delete(Role::Admin, database::purge_cache),
+        )
         .route("/now_playing", get(now_playing::get_now_playing))
         .route(
             "/now_playing/content",
             get(now_playing::get_now_playing_content),
         )
-        .with_state(state)
-        .nest_service("/assets", ServeDir::new("assets"))
+        .nest("/api/v1", api_routes())
+}
+
+fn api_routes() -> Router<AppState> {
+    let actions = Actions { legacy_get: false };
+    Router::new()
+        .route("/artists", get(api::get_artists))
+        .route("/albums", get(api::get_albums))
+        .route("/browse", get(api::browse))
+        .route("/songs", get(api::get_songs))
+        .route("/search", get(api::search))
+        .route("/files", get(api::list_directory))
+        .route(
+            "/radio",
+            get(api::get_stations)
+                .merge(actions.post(Role::User, api::add_station))
+                .merge(actions.delete(Role::User, api::remove_station)),
+        )
+        .route("/status", get(api::get_status))
+        .route("/stats", get(api::get_stats))
+        .route("/volume", get(api::get_volume))
+        .route(
+            "/queue",
+            get(api::get_queue).merge(actions.delete(Role::User, api::clear_queue)),
+        )
+        .route(
+            "/queue/:song_id",
+            actions.delete(Role::User, api::remove_from_queue),
+        )
+        .route(
+            "/queue/:song_id/move",
+            actions.post(Role::User, api::move_in_queue),
+        )
+        .route(
+            "/queue/:song_id/play_next",
+            actions.post(Role::User, api::play_next),
+        )
+        .route(
+            "/queue/move_range",
+            actions.post(Role::User, api::move_range_in_queue),
+        )
+        .route("/queue/album", actions.post(Role::Guest, api::append_album))
+        .route("/queue/song", actions.post(Role::Guest, api::append_song))
+        .route("/playlists", get(api::get_stored_playlists))
+        .route(
+            "/playlists/:name",
+            get(api::get_stored_playlist)
+                .merge(actions.post(Role::User, api::save_queue_as_playlist))
+                .merge(actions.delete(Role::User, api::delete_playlist)),
+        )
+        .route(
+            "/playlists/:name/load",
+            actions.post(Role::User, api::load_playlist),
+        )
+        .route(
+            "/playlists/:name/play",
+            actions.post(Role::User, api::play_playlist),
+        )
+        .route(
+            "/playlists/:name/rename",
+            actions.post(Role::User, api::rename_playlist),
+        )
+        .route(
+            "/playlists/:name/song",
+            actions.post(Role::User, api::add_song_to_playlist),
+        )
+        .route(
+            "/playlists/:name/album",
+            actions.post(Role::User, api::add_album_to_playlist),
+        )
+        .route(
+            "/playlists/:name/song/:position",
+            actions.delete(Role::User, api::remove_from_playlist),
+        )
+        .route("/play/album", actions.post(Role::User, api::play_album))
+        .route("/play/song", actions.post(Role::User, api::play_song))
+        .route("/control/play", actions.post(Role::User, api::control_play))
+        .route(
+            "/control/pause",
+            actions.post(Role::User, api::control_pause),
+        )
+        .route(
+            "/control/unpause",
+            actions.post(Role::User, api::control_unpause),
+        )
+        .route("/control/prev", actions.post(Role::User, api::control_prev))
+        .route("/control/next", actions.post(Role::User, api::control_next))
+        .route("/control/seek", actions.post(Role::User, api::control_seek))
+        .route(
+            "/control/volume",
+            actions.post(Role::User, api::control_volume),
+        )
+        .route(
+            "/control/toggle_mute",
+            actions.post(Role::User, api::toggle_mute),
+        )
+        .route(
+            "/control/toggle_repeat",
+            actions.post(Role::User, api::toggle_repeat),
+        )
+        .route(
+            "/control/toggle_random",
+            actions.post(Role::User, api::toggle_random),
+        )
+        .route(
+            "/control/cycle_single",
+            actions.post(Role::User, api::cycle_single),
+        )
+        .route(
+            "/control/toggle_consume",
+            actions.post(Role::User, api::toggle_consume),
+        )
+        .route(
+            "/control/crossfade",
+            actions.post(Role::User, api::control_crossfade),
+        )
+        .route(
+            "/control/replay_gain",
+            actions.post(Role::User, api::control_replay_gain),
+        )
+        .route(
+            "/database/update",
+            actions.post(Role::Admin, api::update_db),
+        )
+        .route(
+            "/cache",
+            get(api::get_cache).merge(actions.delete(Role::Admin, api::purge_cache)),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Auth;
+    use crate::cache::AlbumArtCache;
+    use crate::config::{Config, GuestAccess};
+    use crate::connection::ConnectionState;
+    use crate::csrf::{random_token, CsrfToken};
+    use crate::mpd::Mpd;
+    use crate::party::Party;
+    use crate::radio::Stations;
+    use crate::snapshot::SnapshotPublisher;
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, watch, Mutex};
+    use tower::ServiceExt;
+
+    /// Router of a single server that is never connected, with its files in `dir`.
+    fn router(dir: &Path) -> Router {
+        let config = Arc::new(Config {
+            admin_password: Some("admin".to_string()),
+            user_password: Some("user".to_string()),
+            guest_access: GuestAccess::Browse,
+            cache_dir: dir.join("cache"),
+            stations_file: dir.join("stations.toml"),
+            ..Config::default()
+        });
+        let server = Arc::new(config.servers().remove(0));
+        let (_, snapshot) = SnapshotPublisher::new();
+        let (_, connection) = watch::channel(ConnectionState::Connecting);
+        let state = AppState {
+            mpd: Mpd::new(config.clone()),
+            album_art_cache: Arc::new(Mutex::new(
+                AlbumArtCache::open(dir.join("cache"), 1024, 10).unwrap(),
+            )),
+            event_tx: broadcast::channel(16).0,
+            prefix: format!("/s/{}", server.name),
+            servers: Arc::new(vec![server.name.clone()]),
+            server,
+            stations: Arc::new(Mutex::new(Stations::load(&config.stations_file).unwrap())),
+            snapshot,
+            connection,
+            csrf_token: CsrfToken::generate().unwrap(),
+            auth: Auth::new(config.clone()),
+            party: Arc::new(Party::new(config.clone())),
+            config,
+        };
+        create_router(vec![state])
+    }
+
+    async fn status(
+        router: &Router,
+        method: &str,
+        uri: &str,
+        password: Option<&str>,
+    ) -> StatusCode {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(password) = password {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {password}"));
+        }
+        router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("rump-test-{}", random_token().unwrap()))
+    }
+
+    #[tokio::test]
+    async fn ui_actions_require_their_role() {
+        let dir = temp_dir();
+        let router = router(&dir);
+        let add = "/radio/add?name=Radio&url=http%3A%2F%2Fradio.example%2Fstream";
+
+        assert_eq!(
+            status(&router, "POST", add, None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&router, "POST", &format!("/s/default{add}"), None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&router, "POST", add, Some("user")).await,
+            StatusCode::OK
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn api_actions_require_their_role() {
+        let dir = temp_dir();
+        let router = router(&dir);
+
+        assert_eq!(
+            status(&router, "DELETE", "/api/v1/cache", None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&router, "DELETE", "/api/v1/cache", Some("user")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&router, "DELETE", "/api/v1/cache", Some("admin")).await,
+            StatusCode::NO_CONTENT
+        );
+        // reading stays open to guests with `browse`
+        assert_eq!(
+            status(&router, "GET", "/api/v1/cache", None).await,
+            StatusCode::OK
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
 }
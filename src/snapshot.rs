@@ -0,0 +1,87 @@
+use crate::mpd::{Mpd, SongInQueue, Status};
+use mpd_client::client::Subsystem;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/// Status and queue of one server as last read from MPD.
+pub struct Snapshot {
+    pub status: Status,
+    pub queue: Arc<Vec<SongInQueue>>,
+    /// Unix time in milliseconds the status was read at
+    pub timestamp: u64,
+}
+
+pub type SnapshotReceiver = watch::Receiver<Option<Arc<Snapshot>>>;
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64)
+}
+
+/// Publishes a new snapshot of one server whenever it changes. Owned by the task listening to
+/// the server's connection events, every socket reads from a receiver instead of asking MPD.
+pub struct SnapshotPublisher {
+    tx: watch::Sender<Option<Arc<Snapshot>>>,
+}
+
+impl SnapshotPublisher {
+    pub fn new() -> (Self, SnapshotReceiver) {
+        let (tx, rx) = watch::channel(None);
+        (Self { tx }, rx)
+    }
+
+    /// Re-reads what the change of `subsystem` may have affected, or everything if `None`
+    /// (after (re)connecting or when events may have been missed).
+    pub async fn refresh(&self, mpd: &Mpd, subsystem: Option<&Subsystem>) {
+        let queue = match subsystem {
+            None | Some(Subsystem::Queue) => None,
+            // only the status changed, keep the queue; the current song is marked from the status
+            Some(Subsystem::Player | Subsystem::Mixer | Subsystem::Options | Subsystem::Update) => {
+                self.tx
+                    .borrow()
+                    .as_ref()
+                    .map(|snapshot| snapshot.queue.clone())
+            }
+            Some(_) => return,
+        };
+
+        if let Err(e) = self.read(mpd, queue).await {
+            tracing::debug!("Failed to refresh MPD snapshot: {e}");
+        }
+    }
+
+    async fn read(&self, mpd: &Mpd, queue: Option<Arc<Vec<SongInQueue>>>) -> anyhow::Result<()> {
+        let status = mpd.get_status().await?;
+        let timestamp = unix_millis();
+        let queue = match queue {
+            Some(queue) => mark_playing(queue, status.song_position),
+            None => Arc::new(mpd.get_playlist().await?),
+        };
+
+        self.tx.send_replace(Some(Arc::new(Snapshot {
+            status,
+            queue,
+            timestamp,
+        })));
+        Ok(())
+    }
+}
+
+/// The queue with the song at `position` as the one playing, shared if that didn't change.
+fn mark_playing(queue: Arc<Vec<SongInQueue>>, position: Option<usize>) -> Arc<Vec<SongInQueue>> {
+    let is_current = |song: &SongInQueue| Some(song.position) == position;
+    if queue.iter().all(|song| song.playing == is_current(song)) {
+        return queue;
+    }
+    Arc::new(
+        queue
+            .iter()
+            .map(|song| SongInQueue {
+                playing: is_current(song),
+                ..song.clone()
+            })
+            .collect(),
+    )
+}
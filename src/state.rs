@@ -1,12 +1,49 @@
+use crate::auth::Auth;
 use crate::cache::AlbumArtCache;
+use crate::config::{Config, ServerConfig};
+use crate::connection::ConnectionState;
+use crate::csrf::CsrfToken;
 use crate::mpd::Mpd;
+use crate::party::Party;
+use crate::radio::Stations;
+use crate::snapshot::SnapshotReceiver;
+use crate::templates::TabsTemplate;
 use mpd_client::client::Subsystem;
 use std::sync::Arc;
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::{broadcast, watch, Mutex};
 
+/// State of a single MPD server. Every server gets its own router nested under `prefix`, so
+/// handlers only ever see the server they were reached through.
 #[derive(Clone)]
 pub struct AppState {
     pub mpd: Mpd,
     pub album_art_cache: Arc<Mutex<AlbumArtCache>>,
     pub event_tx: broadcast::Sender<Subsystem>,
+    pub config: Arc<Config>,
+    pub server: Arc<ServerConfig>,
+    /// URL prefix of this server's routes, e.g. `/s/kitchen` (empty for the root)
+    pub prefix: String,
+    /// Names of all configured servers
+    pub servers: Arc<Vec<String>>,
+    /// Favourite radio stations, shared by all servers
+    pub stations: Arc<Mutex<Stations>>,
+    /// Latest status and queue, read once per change for all sockets
+    pub snapshot: SnapshotReceiver,
+    pub connection: watch::Receiver<ConnectionState>,
+    /// Shared by all servers
+    pub csrf_token: CsrfToken,
+    pub auth: Auth,
+    /// Song requests of party mode
+    pub party: Arc<Party>,
+}
+
+impl AppState {
+    pub fn tabs(&self) -> TabsTemplate {
+        TabsTemplate {
+            servers: self.servers.clone(),
+            current_server: self.server.name.clone(),
+            auth: self.config.admin_password.is_some(),
+            ..Default::default()
+        }
+    }
 }
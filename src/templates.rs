@@ -1,6 +1,17 @@
-use crate::mpd::{Album, Song, SongInQueue, Status};
+use crate::auth::Role;
+use crate::cache::CacheStats;
+use crate::config::GuestAccess;
+use crate::connection::ConnectionState;
+use crate::models::{Browse, SearchTag};
+use crate::mpd::{
+    Album, ArtistTag, Directory, SearchResults, Song, SongInQueue, Status, StoredPlaylist,
+    StoredPlaylistSong,
+};
+use crate::party::RequestView;
+use crate::radio::Station;
 use askama::Template;
 use itertools::Itertools;
+use std::sync::Arc;
 
 pub enum Page {
     Library(LibraryTemplate),
@@ -9,6 +20,12 @@ pub enum Page {
     NowPlaying(NowPlayingTemplate),
     Database(DatabaseTemplate),
     Playlist(PlaylistTemplate),
+    StoredPlaylists(StoredPlaylistsTemplate),
+    StoredPlaylist(StoredPlaylistTemplate),
+    Search(SearchTemplate),
+    Files(FilesTemplate),
+    Radio(RadioTemplate),
+    Party(PartyTemplate),
 }
 
 impl std::fmt::Display for Page {
@@ -20,6 +37,12 @@ impl std::fmt::Display for Page {
             Page::NowPlaying(p) => p.fmt(f),
             Page::Database(p) => p.fmt(f),
             Page::Playlist(p) => p.fmt(f),
+            Page::StoredPlaylists(p) => p.fmt(f),
+            Page::StoredPlaylist(p) => p.fmt(f),
+            Page::Search(p) => p.fmt(f),
+            Page::Files(p) => p.fmt(f),
+            Page::Radio(p) => p.fmt(f),
+            Page::Party(p) => p.fmt(f),
         }
     }
 }
@@ -28,8 +51,22 @@ impl std::fmt::Display for Page {
 #[template(path = "index.html")]
 pub struct IndexTemplate {
     pub error: Option<String>,
+    /// The error is about the connection, so reload the page once it is back
+    pub disconnected: bool,
+    pub csrf_token: String,
     pub page: Page,
     pub tabs: TabsTemplate,
+    pub prefix: String,
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct LoginTemplate {
+    pub role: Role,
+    pub guest_access: GuestAccess,
+    pub error: Option<String>,
+    /// Sent with the forms, which can't set `X-CSRF-Token`
+    pub csrf_token: String,
 }
 
 #[derive(Template, Default)]
@@ -37,50 +74,75 @@ pub struct IndexTemplate {
 pub struct TabsTemplate {
     pub library_active: bool,
     pub playlist_active: bool,
+    pub stored_playlists_active: bool,
+    pub search_active: bool,
+    pub files_active: bool,
+    pub radio_active: bool,
     pub database_active: bool,
     pub now_playing_active: bool,
+    pub party_active: bool,
+    pub servers: Arc<Vec<String>>,
+    pub current_server: String,
+    /// Show the login link
+    pub auth: bool,
 }
 
 #[derive(Template)]
 #[template(path = "library.html")]
 pub struct LibraryTemplate {
     pub tabs: Option<TabsTemplate>,
-    pub artists: Vec<(char, Vec<String>)>,
+    pub groups: Vec<(String, Vec<String>)>,
     pub query: String,
+    pub by: Browse,
 }
 
-fn first_letter(s: &str) -> char {
+fn first_letter(s: &str) -> String {
     s.chars()
         .next()
         .unwrap_or_default()
         .to_uppercase()
         .next()
         .unwrap_or_default()
+        .to_string()
+}
+
+fn decade(year: &str) -> String {
+    match year.get(..3) {
+        Some(decade) if decade.chars().all(|c| c.is_ascii_digit()) => format!("{decade}0s"),
+        _ => "Unknown".to_string(),
+    }
 }
 
 impl LibraryTemplate {
+    /// Years are always grouped by decade, other values by first letter when there are many.
     pub fn new(
         tabs: Option<TabsTemplate>,
-        artists_vec: Vec<String>,
+        values: Vec<String>,
         query: Option<String>,
+        by: Browse,
     ) -> Self {
-        let mut artists_vec = artists_vec.clone();
-        artists_vec.sort_by_key(|a| a.to_lowercase());
+        let mut values = values;
+        values.sort_by_key(|a| a.to_lowercase());
 
-        let artists = if artists_vec.len() <= 20 {
-            vec![(' ', artists_vec)]
-        } else {
-            artists_vec
+        let group_key: Option<fn(&str) -> String> = match by {
+            Browse::Date => Some(decade),
+            _ if values.len() > 20 => Some(first_letter),
+            _ => None,
+        };
+        let groups = match group_key {
+            Some(group_key) => values
                 .into_iter()
-                .group_by(|artist| first_letter(artist))
+                .group_by(|value| group_key(value))
                 .into_iter()
                 .map(|(key, group)| (key, group.collect_vec()))
-                .collect_vec()
+                .collect_vec(),
+            None => vec![(" ".to_string(), values)],
         };
         Self {
             tabs,
-            artists,
+            groups,
             query: query.unwrap_or_default(),
+            by,
         }
     }
 }
@@ -89,17 +151,96 @@ impl LibraryTemplate {
 #[template(path = "albums.html")]
 pub struct AlbumsTemplate {
     pub tabs: Option<TabsTemplate>,
-    pub artist: String,
+    pub title: String,
+    pub by: Browse,
     pub albums: Vec<Album>,
 }
 
+#[derive(Template)]
+#[template(path = "search.html")]
+pub struct SearchTemplate {
+    pub tabs: Option<TabsTemplate>,
+    pub query: String,
+    pub tag: SearchTag,
+    pub results: SearchResults,
+}
+
+#[derive(Template)]
+#[template(path = "files.html")]
+pub struct FilesTemplate {
+    pub tabs: Option<TabsTemplate>,
+    pub directory: Directory,
+}
+
+impl FilesTemplate {
+    /// Name and path of every directory from the root down to the current one.
+    pub fn breadcrumbs(&self) -> Vec<(String, String)> {
+        let mut path = String::new();
+        self.directory
+            .path
+            .split('/')
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                if !path.is_empty() {
+                    path.push('/');
+                }
+                path.push_str(name);
+                (name.to_string(), path.clone())
+            })
+            .collect()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "radio.html")]
+pub struct RadioTemplate {
+    pub tabs: Option<TabsTemplate>,
+    pub stations: Vec<Station>,
+}
+
+#[derive(Template)]
+#[template(path = "radio_stations.html")]
+pub struct RadioStationsTemplate {
+    pub stations: Vec<Station>,
+}
+
+#[derive(Template)]
+#[template(path = "party.html")]
+pub struct PartyTemplate {
+    pub tabs: Option<TabsTemplate>,
+    pub requests: PartyRequestsTemplate,
+}
+
+#[derive(Template)]
+#[template(path = "party_search.html")]
+pub struct PartySearchTemplate {
+    pub query: String,
+    pub results: SearchResults,
+}
+
+#[derive(Template)]
+#[template(path = "party_requests.html")]
+pub struct PartyRequestsTemplate {
+    pub requests: Vec<RequestView>,
+    /// Show the approve and remove buttons
+    pub admin: bool,
+    /// Guests' requests wait for approval
+    pub approval: bool,
+}
+
 #[derive(Template)]
 #[template(path = "status.html")]
-pub struct StatusTemplate {
-    pub status: Status,
+pub struct StatusTemplate<'a> {
+    pub status: &'a Status,
     pub background: Gradient,
 }
 
+#[derive(Template)]
+#[template(path = "connection.html")]
+pub struct ConnectionTemplate<'a> {
+    pub state: &'a ConnectionState,
+}
+
 #[derive(Clone, Copy)]
 pub struct Gradient {
     pub start: Color,
@@ -144,13 +285,42 @@ impl Color {
 #[template(path = "playlist.html")]
 pub struct PlaylistTemplate {
     pub tabs: Option<TabsTemplate>,
+    pub prefix: String,
 }
 
 #[derive(Template)]
 #[template(path = "playlist_songs.html")]
-pub struct PlaylistSongsTemplate {
-    pub songs: Vec<SongInQueue>,
-    pub status: Status,
+pub struct PlaylistSongsTemplate<'a> {
+    pub songs: &'a [SongInQueue],
+    pub status: &'a Status,
+}
+
+#[derive(Template)]
+#[template(path = "stored_playlists.html")]
+pub struct StoredPlaylistsTemplate {
+    pub tabs: Option<TabsTemplate>,
+    pub prefix: String,
+}
+
+#[derive(Template)]
+#[template(path = "stored_playlists_list.html")]
+pub struct StoredPlaylistsListTemplate {
+    pub playlists: Vec<StoredPlaylist>,
+}
+
+#[derive(Template)]
+#[template(path = "stored_playlist.html")]
+pub struct StoredPlaylistTemplate {
+    pub tabs: Option<TabsTemplate>,
+    pub prefix: String,
+    pub name: String,
+}
+
+#[derive(Template)]
+#[template(path = "stored_playlist_songs.html")]
+pub struct StoredPlaylistSongsTemplate {
+    pub name: String,
+    pub songs: Vec<StoredPlaylistSong>,
 }
 
 #[derive(Template)]
@@ -158,16 +328,55 @@ pub struct PlaylistSongsTemplate {
 pub struct AlbumSongsTemplate {
     pub tabs: Option<TabsTemplate>,
     pub artist: String,
+    pub artist_tag: ArtistTag,
     pub album: String,
     pub songs: Vec<Song>,
 }
 
+impl AlbumSongsTemplate {
+    /// Songs grouped by disc number; a single group without number unless the album has several
+    /// discs.
+    pub fn discs(&self) -> Vec<(Option<u64>, Vec<&Song>)> {
+        if self.songs.iter().map(|song| song.disc).all_equal() {
+            return vec![(None, self.songs.iter().collect())];
+        }
+        self.songs
+            .iter()
+            .group_by(|song| song.disc)
+            .into_iter()
+            .map(|(disc, songs)| (disc, songs.collect()))
+            .collect()
+    }
+
+    /// Total length in seconds.
+    pub fn total_duration(&self) -> u64 {
+        self.songs
+            .iter()
+            .filter_map(|song| song.duration)
+            .sum::<f64>() as u64
+    }
+
+    pub fn year(&self) -> Option<i32> {
+        self.songs.iter().filter_map(|song| song.year).min()
+    }
+
+    pub fn genres(&self) -> String {
+        self.songs
+            .iter()
+            .filter_map(|song| song.genre.as_deref())
+            .unique()
+            .join(", ")
+    }
+}
+
 #[derive(Template)]
 #[template(path = "database.html")]
 pub struct DatabaseTemplate {
     pub tabs: Option<TabsTemplate>,
     pub mpd_addr: String,
-    pub stats: mpd_client::responses::Stats,
+    /// `None` on full page loads while MPD is unavailable
+    pub stats: Option<mpd_client::responses::Stats>,
+    pub cache: CacheStats,
 }
 
 #[derive(Template)]
@@ -176,19 +385,34 @@ pub struct DatabaseUpdateStatusTemplate {
     pub updating: bool,
 }
 
+#[derive(Template)]
+#[template(path = "database_cache.html")]
+pub struct DatabaseCacheTemplate {
+    pub cache: CacheStats,
+}
+
 #[derive(Template)]
 #[template(path = "now_playing.html")]
 pub struct NowPlayingTemplate {
     pub tabs: Option<TabsTemplate>,
+    pub prefix: String,
 }
 
 #[derive(Template)]
 #[template(path = "now_playing_content.html")]
-pub struct NowPlayingContentTemplate {
-    pub status: Status,
+pub struct NowPlayingContentTemplate<'a> {
+    pub status: &'a Status,
+    /// Unix time in milliseconds the status was read at
+    pub timestamp: u64,
+    /// Unix time in milliseconds of rendering, lets clients account for the age of `status`
+    pub now: u64,
 }
 
-impl NowPlayingContentTemplate {
+impl NowPlayingContentTemplate<'_> {
+    pub fn playing(&self) -> bool {
+        self.status.play_state == mpd_client::responses::PlayState::Playing
+    }
+
     pub fn progress(&self) -> Option<f64> {
         if let Some(elapsed) = &self.status.elapsed {
             if let Some(duration) = &self.status.duration {
@@ -217,6 +441,22 @@ mod filters {
         Ok(format!("{:0>2}:{:0>2}", minutes, seconds))
     }
 
+    pub fn bytes(bytes: &u64) -> ::askama::Result<String> {
+        if *bytes < 1024 {
+            return Ok(format!("{bytes} B"));
+        }
+        let mut value = *bytes as f64 / 1024.0;
+        let mut unit = "KiB";
+        for next in ["MiB", "GiB"] {
+            if value < 1024.0 {
+                break;
+            }
+            value /= 1024.0;
+            unit = next;
+        }
+        Ok(format!("{value:.1} {unit}"))
+    }
+
     pub fn datetime(unix_timestamp: &u64) -> ::askama::Result<String> {
         let secs = *unix_timestamp as i64;
         let datetime = DateTime::from_timestamp(secs, 0).ok_or(::askama::Error::Fmt(Error))?;